@@ -0,0 +1,252 @@
+use std::io;
+use std::process::{Command, Stdio};
+use std::os::unix::process::CommandExt;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use nix::sys::signal::*;
+use nix::unistd::{setpgid, Pid};
+use serde::Deserialize;
+use crate::alert::AlertChange;
+use crate::errors::*;
+
+// Delay between two attempts of a failed action
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+fn default_timeout() -> u64 { 10 }
+fn default_retries() -> usize { 2 }
+
+/// What to do when an alert fires or clears: run `command` with the alert
+/// in PEMON_ALERT_* environment variables, and/or POST it as JSON to `webhook`.
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ActionConfig {
+    pub command: Option<String>,
+    pub webhook: Option<String>,
+    // Seconds one attempt may take
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default = "default_retries")]
+    pub retries: usize,
+}
+
+/// Runs the actions in their own threads, so that a hung hook never
+/// delays the next sample.
+pub struct ActionRunner {
+    config: ActionConfig,
+    running: Vec<JoinHandle<()>>,
+}
+
+fn get_env(change: &AlertChange) -> Vec<(&'static str, String)> {
+    vec![
+        ("PEMON_ALERT_NAME", change.name.clone()),
+        ("PEMON_ALERT_METRIC", change.metric.clone()),
+        ("PEMON_ALERT_SEVERITY", change.severity.to_string()),
+        ("PEMON_ALERT_STATE", if change.fired { "fired" } else { "cleared" }.to_string()),
+        ("PEMON_ALERT_VALUE", change.value.to_string()),
+        ("PEMON_ALERT_THRESHOLD", change.threshold.to_string()),
+        ("PEMON_ALERT_TIME", change.time.as_secs().to_string()),
+    ]
+}
+
+fn get_payload(change: &AlertChange) -> String {
+    serde_json::json!({
+        "name": change.name,
+        "metric": change.metric,
+        "severity": change.severity.to_string(),
+        "state": if change.fired { "fired" } else { "cleared" },
+        "value": change.value,
+        "threshold": change.threshold,
+        "time": change.time.as_secs(),
+    }).to_string()
+}
+
+fn run_command(command: &str, env: &[(&str, String)], timeout: Duration) -> Result<()> {
+    let mut child;
+    unsafe {
+        child = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .envs(env.iter().map(|(k, v)| (k, v)))
+                .stdin(Stdio::null())
+                // pre_exec is unsafe function
+                .pre_exec(|| {
+                    let mut set = SigSet::empty();
+                    set.add(SIGINT);
+                    set.add(SIGTERM);
+                    // Panicking in the forked child isn't safe, so failures are returned to spawn()
+                    sigprocmask(SigmaskHow::SIG_BLOCK, Some(&set), None).map_err(|_| io::Error::last_os_error())?;
+                    // Its own process group, so that a timeout also kills what the shell started
+                    setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(|_| io::Error::last_os_error())?;
+                    Ok(())
+                })
+                .spawn()?;
+    }
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                bail!("Alert command '{}' failed: {}", command, status);
+            }
+            return Ok(());
+        }
+        if start.elapsed() >= timeout {
+            let _ = killpg(Pid::from_raw(child.id() as i32), SIGKILL);
+            let _ = child.wait();
+            bail!("Alert command '{}' timed out after {}s.", command, timeout.as_secs());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn post_webhook(url: &str, payload: &str, timeout: Duration) -> Result<()> {
+    match ureq::post(url).timeout(timeout).set("Content-Type", "application/json").send_string(payload) {
+        Ok(_) => Ok(()),
+        Err(e) => bail!("POST to {} failed: {}", url, e),
+    }
+}
+
+fn with_retries<F: Fn() -> Result<()>>(retries: usize, f: F) {
+    for attempt in 0..=retries {
+        match f() {
+            Ok(()) => return,
+            Err(e) => warn!("Alert action failed (attempt {} of {}): {}", attempt + 1, retries + 1, e),
+        }
+        if attempt < retries {
+            thread::sleep(RETRY_DELAY);
+        }
+    }
+}
+
+impl ActionRunner {
+    pub fn new(config: ActionConfig) -> ActionRunner {
+        ActionRunner { config, running: Vec::new() }
+    }
+
+    /// Use the actions of a reloaded config from the next alert on, those
+    /// already running carry on.
+    pub fn reload(&mut self, config: ActionConfig) {
+        self.config = config;
+    }
+
+    /// Start the actions of one fired or cleared alert.
+    pub fn run(&mut self, change: &AlertChange) {
+        self.running.retain(|h| !h.is_finished());
+        let timeout = Duration::from_secs(self.config.timeout);
+        let retries = self.config.retries;
+        if let Some(command) = self.config.command.clone() {
+            let env = get_env(change);
+            self.running.push(thread::spawn(move || with_retries(retries, || run_command(&command, &env, timeout))));
+        }
+        if let Some(url) = self.config.webhook.clone() {
+            let payload = get_payload(change);
+            self.running.push(thread::spawn(move || with_retries(retries, || post_webhook(&url, &payload, timeout))));
+        }
+    }
+
+    /// Wait for the running actions, which is bounded by their timeouts.
+    pub fn finish(&mut self) {
+        for h in self.running.drain(..) {
+            let _ = h.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use crate::alert::Severity;
+
+    fn change() -> AlertChange {
+        AlertChange {
+            name: "CPU hot".to_string(), metric: "cpu_temp".to_string(), severity: Severity::Crit, fired: true,
+            time: Duration::from_secs(42), value: 96.5, threshold: 95.0,
+        }
+    }
+
+    // Answer the first `fails` requests with 500, then 200, and return the bodies
+    fn stub_server(fails: usize, requests: usize) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for i in 0..requests {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(l) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = l.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                let status = if i < fails { "500 Internal Server Error" } else { "200 OK" };
+                write!(reader.get_mut(), "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            }
+            bodies
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_webhook() {
+        let (url, server) = stub_server(1, 2);
+        let mut runner = ActionRunner::new(ActionConfig { command: None, webhook: Some(url), timeout: 5, retries: 2 });
+        runner.run(&change());
+        runner.finish();
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies.len(), 2);
+        let payload: serde_json::Value = serde_json::from_str(&bodies[1]).unwrap();
+        assert_eq!(payload["name"], "CPU hot");
+        assert_eq!(payload["severity"], "crit");
+        assert_eq!(payload["state"], "fired");
+        assert_eq!(payload["value"], 96.5);
+        assert_eq!(payload["time"], 42);
+    }
+
+    #[test]
+    fn test_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("alert");
+        let command = format!("echo \"$PEMON_ALERT_NAME|$PEMON_ALERT_SEVERITY|$PEMON_ALERT_STATE|$PEMON_ALERT_VALUE\" > {}",
+                              out.display());
+        let mut runner = ActionRunner::new(ActionConfig { command: Some(command), webhook: None, timeout: 5, retries: 0 });
+        runner.run(&change());
+        runner.finish();
+        assert_eq!(fs::read_to_string(&out).unwrap(), "CPU hot|crit|fired|96.5\n");
+
+        // A hung hook is killed after the timeout, and running it doesn't block
+        let start = Instant::now();
+        let mut runner = ActionRunner::new(ActionConfig {
+            command: Some("sleep 30".to_string()), webhook: None, timeout: 1, retries: 1,
+        });
+        runner.run(&change());
+        assert!(start.elapsed() < Duration::from_millis(500));
+        runner.finish();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(run_command("exit 3", &[], Duration::from_secs(5)).is_err());
+
+        // The processes the shell started are killed too
+        let pid_file = dir.path().join("pid");
+        assert!(run_command(&format!("sleep 30 & echo $! > {}; wait; true", pid_file.display()), &[],
+                            Duration::from_secs(1)).is_err());
+        let pid = fs::read_to_string(&pid_file).unwrap().trim().to_string();
+        let gone = (0..100).any(|_| {
+            // Dead, or a zombie nobody has reaped yet
+            let gone = fs::read_to_string(format!("/proc/{}/stat", pid))
+                .map_or(true, |s| s[s.rfind(')').unwrap() + 2..].starts_with('Z'));
+            thread::sleep(Duration::from_millis(10));
+            gone
+        });
+        assert!(gone, "sleep {} survived the timeout", pid);
+    }
+}
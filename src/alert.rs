@@ -0,0 +1,413 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
+use std::time::Duration;
+use serde::Deserialize;
+use crate::util::*;
+
+/// The metrics alert rules can refer to. "*" stands for a CPU id, a device
+/// like "sda" or "eth0", a PSI resource like "CPU" or "cgroup_IO", a thermal
+/// zone or cooling device, an idle state, or a hwmon sensor name. Spaces in
+/// those are replaced with '_', e.g. "thermal.x86_pkg_temp_0" or
+/// "hwmon.k10temp_Tctl". Temperatures are in °C, fans in RPM, rates per
+/// second, sizes in MiB and the rest in % unless the name says otherwise.
+pub const METRICS: &[&str] = &[
+    // Board sensors, missing if the profile doesn't map them
+    "cpu_temp", "mb_temp", "chipset_temp", "cpu_fan_rpm", "chassis_fan_rpm", "hdd_temp",
+    "cpu_freq", "cpu_usage", "cpu*.freq", "cpu*.usage", "cpu*.idle.*",
+    // With RAPL only
+    "package_watts", "core_watts", "dram_watts",
+    "memory_total", "memory_used", "memory_available", "memory_cached", "memory_dirty",
+    "swap_total", "swap_used", "fault_rate", "major_fault_rate", "swapin_rate", "swapout_rate",
+    "load1", "load5", "load15", "procs_running", "procs_blocked", "threads", "ctxt_rate", "intr_rate", "fork_rate",
+    // latency is missing in samples without a request
+    "disk.*.read_mbps", "disk.*.write_mbps", "disk.*.iops", "disk.*.latency", "disk.*.util",
+    "net.*.rx_mbps", "net.*.tx_mbps", "net.*.rx_packets", "net.*.tx_packets", "net.*.errors", "net.*.dropped",
+    // full_* is missing for the CPU before Linux 5.13
+    "pressure.*.some_avg10", "pressure.*.some_avg60", "pressure.*.some_avg300", "pressure.*.some_stall",
+    "pressure.*.full_avg10", "pressure.*.full_avg60", "pressure.*.full_avg300", "pressure.*.full_stall",
+    // The zone temperature, and the margins to its trip points if it has them
+    "thermal.*", "thermal.*.passive_margin", "thermal.*.critical_margin", "cooling.*.state",
+    "hwmon.*",
+    // With --pid only
+    "process.processes", "process.usage", "process.rss", "process.threads", "process.voluntary_rate",
+    "process.involuntary_rate", "process.read_mbps", "process.write_mbps",
+    // With --cgroup only, throttled_periods needs a CPU quota and memory the memory controller
+    "cgroup.usage", "cgroup.throttled_periods", "cgroup.throttled_ms", "cgroup.memory", "cgroup.anon", "cgroup.file",
+    "cgroup.read_mbps", "cgroup.write_mbps", "cgroup.iops",
+];
+
+/// The metric name of a device or sensor name, e.g. "x86_pkg_temp 0" becomes
+/// "x86_pkg_temp_0".
+pub fn metric_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<&str>>().join("_")
+}
+
+/// Whether `name` is one of METRICS.
+pub fn is_known_metric(name: &str) -> bool {
+    !name.is_empty() && !name.contains(char::is_whitespace) && METRICS.iter().any(|m| wildcard_match(m, name))
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Severity {
+    Warn,
+    Crit,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self { Severity::Warn => "warn", Severity::Crit => "crit" })
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Op {
+    Above,
+    Below,
+}
+
+/// A condition like "> 85 for 30s": the metric must stay above (or below)
+/// the threshold for the hold time before the alert fires.
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(try_from = "String")]
+pub struct Condition {
+    op: Op,
+    threshold: f64,
+    hold: Duration,
+}
+
+impl TryFrom<String> for Condition {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Condition, String> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let op = match fields.first() {
+            Some(&">") => Op::Above,
+            Some(&"<") => Op::Below,
+            _ => return Err(format!("condition '{}' must start with '>' or '<'", s)),
+        };
+        let threshold = match fields.get(1).and_then(|t| t.parse::<f64>().ok()) {
+            Some(t) if t.is_finite() => t,
+            _ => return Err(format!("condition '{}' has no valid threshold", s)),
+        };
+        let hold = match &fields[2..] {
+            [] => Duration::from_secs(0),
+            ["for", hold] => {
+                let (n, unit) = hold.split_at(hold.find(|c: char| !c.is_ascii_digit()).unwrap_or(hold.len()));
+                let n = n.parse::<u64>().map_err(|_| format!("condition '{}' has an invalid hold time", s))?;
+                match unit {
+                    "s" => Duration::from_secs(n),
+                    "m" => match n.checked_mul(60) {
+                        Some(secs) => Duration::from_secs(secs),
+                        None => return Err(format!("condition '{}' has a hold time out of range", s)),
+                    },
+                    _ => return Err(format!("hold time of condition '{}' must end with 's' or 'm'", s)),
+                }
+            },
+            _ => return Err(format!("condition '{}' must look like '> 85 for 30s'", s)),
+        };
+        Ok(Condition { op, threshold, hold })
+    }
+}
+
+impl Condition {
+    fn triggered(&self, value: f64) -> bool {
+        match self.op {
+            Op::Above => value > self.threshold,
+            Op::Below => value < self.threshold,
+        }
+    }
+
+    fn worse(&self, value: f64, than: f64) -> bool {
+        match self.op {
+            Op::Above => value > than,
+            Op::Below => value < than,
+        }
+    }
+
+    // An active alert clears once the value is back past the threshold by `hysteresis`
+    fn cleared(&self, value: f64, hysteresis: f64) -> bool {
+        match self.op {
+            Op::Above => value <= self.threshold - hysteresis,
+            Op::Below => value >= self.threshold + hysteresis,
+        }
+    }
+}
+
+/// An alert rule of the config file, with a warn and/or a crit condition on
+/// one metric.
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    pub name: Option<String>,
+    pub metric: String,
+    pub warn: Option<Condition>,
+    pub crit: Option<Condition>,
+    #[serde(default)]
+    pub hysteresis: f64,
+}
+
+impl AlertRule {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.metric)
+    }
+}
+
+/// One fired alert, `end` is None if it was still active when monitoring stopped.
+#[derive(PartialEq, Debug, Clone)]
+pub struct AlertEvent {
+    pub name: String,
+    pub metric: String,
+    pub severity: Severity,
+    pub start: Duration,
+    pub end: Option<Duration>,
+    // The furthest value past the threshold while the alert was active
+    pub peak: f64,
+}
+
+/// An alert which fired, or cleared, in the last sample.
+#[derive(PartialEq, Debug, Clone)]
+pub struct AlertChange {
+    pub name: String,
+    pub metric: String,
+    pub severity: Severity,
+    pub fired: bool,
+    pub time: Duration,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+#[derive(Default)]
+struct ConditionState {
+    // When the condition started to hold
+    pending: Option<Duration>,
+    // Index of the active event
+    active: Option<usize>,
+}
+
+pub struct AlertMonitor {
+    rules: Vec<AlertRule>,
+    states: Vec<[ConditionState; 2]>,
+    // Rules whose metric was missing, so that it is only reported once
+    missing: Vec<bool>,
+    pub events: Vec<AlertEvent>,
+}
+
+impl AlertMonitor {
+    pub fn new(rules: Vec<AlertRule>) -> AlertMonitor {
+        let states = rules.iter().map(|_| Default::default()).collect();
+        let missing = vec![false; rules.len()];
+        AlertMonitor { rules, states, missing, events: Vec::new() }
+    }
+
+    /// Evaluate the rules on the metrics of one sample.
+    pub fn update(&mut self, time: Duration, metrics: &BTreeMap<String, f64>) -> Vec<AlertChange> {
+        let mut changes = Vec::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            let value = match metrics.get(&rule.metric) {
+                Some(v) => *v,
+                None => {
+                    if !self.missing[i] {
+                        warn!("Alert {}: metric {} is not available.", rule.name(), rule.metric);
+                        self.missing[i] = true;
+                    }
+                    continue;
+                },
+            };
+            let conditions = [(Severity::Warn, &rule.warn), (Severity::Crit, &rule.crit)];
+            for ((severity, condition), state) in conditions.iter().zip(self.states[i].iter_mut()) {
+                let condition = match condition {
+                    Some(c) => c,
+                    None => continue,
+                };
+                let change = |fired| AlertChange {
+                    name: rule.name().to_string(),
+                    metric: rule.metric.clone(),
+                    severity: *severity,
+                    fired,
+                    time,
+                    value,
+                    threshold: condition.threshold,
+                };
+                match state.active {
+                    Some(e) => {
+                        let event = &mut self.events[e];
+                        if condition.cleared(value, rule.hysteresis) {
+                            info!("Alert {} ({}) cleared at {}: {} = {}", rule.name(), severity,
+                                  format_elapsed(time), rule.metric, value);
+                            event.end = Some(time);
+                            state.active = None;
+                            state.pending = None;
+                            changes.push(change(false));
+                        } else if condition.worse(value, event.peak) {
+                            event.peak = value;
+                        }
+                    },
+                    None if condition.triggered(value) => {
+                        let since = *state.pending.get_or_insert(time);
+                        if time - since >= condition.hold {
+                            let message = format!("Alert {} ({}) fired at {}: {} = {}", rule.name(), severity,
+                                                  format_elapsed(time), rule.metric, value);
+                            match severity {
+                                Severity::Warn => warn!("{}", message),
+                                Severity::Crit => error!("{}", message),
+                            }
+                            state.active = Some(self.events.len());
+                            self.events.push(AlertEvent {
+                                name: rule.name().to_string(),
+                                metric: rule.metric.clone(),
+                                severity: *severity,
+                                start: time,
+                                end: None,
+                                peak: value,
+                            });
+                            changes.push(change(true));
+                        }
+                    },
+                    None => state.pending = None,
+                }
+            }
+        }
+        changes
+    }
+
+    /// Forget the events, except the alerts which are still active.
+    pub fn reset(&mut self) {
+        let mut events = Vec::new();
+        for state in self.states.iter_mut().flat_map(|s| s.iter_mut()) {
+            if let Some(e) = state.active {
+                state.active = Some(events.len());
+                events.push(self.events[e].clone());
+            }
+        }
+        self.events = events;
+    }
+
+    /// Switch to the rules of a reloaded config. An unchanged rule keeps its
+    /// state, the active alerts of a changed or removed rule end at `time`.
+    pub fn reload(&mut self, rules: Vec<AlertRule>, time: Duration) {
+        let mut states = Vec::new();
+        for rule in &rules {
+            match self.rules.iter().position(|r| r == rule) {
+                Some(i) => states.push(mem::take(&mut self.states[i])),
+                None => states.push(Default::default()),
+            }
+        }
+        for (rule, state) in self.rules.iter().zip(&self.states) {
+            for e in state.iter().filter_map(|s| s.active) {
+                info!("Alert {} ({}) ended by the config reload.", rule.name(), self.events[e].severity);
+                self.events[e].end = Some(time);
+            }
+        }
+        self.missing = vec![false; rules.len()];
+        self.rules = rules;
+        self.states = states;
+    }
+}
+
+pub fn do_alert_statistic(events: &[AlertEvent]) -> String {
+    if events.is_empty() {
+        return "Alerts:\t\t\tno alert fired".to_string();
+    }
+    let mut ret = vec![format!("Alerts:\t\t\t{} alert(s) fired", events.len())];
+    for (i, e) in events.iter().enumerate() {
+        let end = match e.end {
+            Some(end) => format!("cleared: {} | duration: {}s", format_elapsed(end), (end - e.start).as_secs()),
+            None => "still active".to_string(),
+        };
+        ret.push(format!("Alert #{}:\t\t{} | {} | fired: {} | {} | peak {}: {:.2}",
+                         i + 1, e.severity, e.name, format_elapsed(e.start), end, e.metric, e.peak));
+    }
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(metric: &str, value: f64) -> BTreeMap<String, f64> {
+        let mut result = BTreeMap::new();
+        result.insert(metric.to_string(), value);
+        result
+    }
+
+    #[test]
+    fn test_alert_monitor() {
+        let rule: AlertRule = toml::from_str("name = \"CPU hot\"\nmetric = \"cpu_temp\"\nwarn = \"> 85 for 6s\"\n\
+                                              crit = \"> 95\"\nhysteresis = 3.0\n").unwrap();
+        let fan: AlertRule = toml::from_str("metric = \"cpu_fan_rpm\"\nwarn = \"< 300 for 3s\"\n").unwrap();
+        let mut monitor = AlertMonitor::new(vec![rule, fan]);
+        let temps = [80.0, 86.0, 90.0, 92.0, 84.0, 88.5, 96.5, 90.0, 83.5, 82.0];
+        for (i, t) in temps.iter().enumerate() {
+            let mut metrics = sample("cpu_temp", *t);
+            metrics.insert("cpu_fan_rpm".to_string(), 1200.0);
+            monitor.update(Duration::from_secs(i as u64 * 3), &metrics);
+        }
+        // The dip to 84 is within the hysteresis, so the warn alert doesn't flap
+        assert_eq!(monitor.events, vec![
+            AlertEvent {
+                name: "CPU hot".to_string(), metric: "cpu_temp".to_string(), severity: Severity::Warn,
+                start: Duration::from_secs(9), end: Some(Duration::from_secs(27)), peak: 96.5,
+            },
+            AlertEvent {
+                name: "CPU hot".to_string(), metric: "cpu_temp".to_string(), severity: Severity::Crit,
+                start: Duration::from_secs(18), end: Some(Duration::from_secs(21)), peak: 96.5,
+            },
+        ]);
+        assert!(monitor.update(Duration::from_secs(30), &sample("cpu_fan_rpm", 250.0)).is_empty());
+        assert_eq!(monitor.update(Duration::from_secs(33), &sample("cpu_fan_rpm", 0.0)), vec![AlertChange {
+            name: "cpu_fan_rpm".to_string(), metric: "cpu_fan_rpm".to_string(), severity: Severity::Warn, fired: true,
+            time: Duration::from_secs(33), value: 0.0, threshold: 300.0,
+        }]);
+        let report = do_alert_statistic(&monitor.events);
+        assert!(report.starts_with("Alerts:\t\t\t3 alert(s) fired\n\
+                                    Alert #1:\t\twarn | CPU hot | fired: +00:00:09 | cleared: +00:00:27 | \
+                                    duration: 18s | peak cpu_temp: 96.50"));
+        assert!(report.ends_with("Alert #3:\t\twarn | cpu_fan_rpm | fired: +00:00:33 | still active | peak cpu_fan_rpm: 0.00"));
+    }
+
+    #[test]
+    fn test_alert_reload() {
+        let rule = |s: &str| -> AlertRule { toml::from_str(&format!("metric = \"cpu_temp\"\n{}", s)).unwrap() };
+        let mut monitor = AlertMonitor::new(vec![rule("warn = \"> 85\"\n"), rule("crit = \"> 95\"\n")]);
+        let secs = Duration::from_secs;
+        monitor.update(secs(3), &sample("cpu_temp", 96.0));
+        monitor.update(secs(6), &sample("cpu_temp", 90.0));
+        assert_eq!(monitor.events.len(), 2);
+        // The cleared crit alert is forgotten, the active warn one is kept
+        monitor.reset();
+        assert_eq!(monitor.events.len(), 1);
+        assert_eq!((monitor.events[0].severity, monitor.events[0].end), (Severity::Warn, None));
+
+        monitor.reload(vec![rule("warn = \"> 85\"\n"), rule("warn = \"> 80\"\n")], secs(9));
+        assert_eq!(monitor.events.len(), 1);
+        assert_eq!(monitor.events[0].end, None);
+        let changes = monitor.update(secs(12), &sample("cpu_temp", 82.0));
+        assert_eq!(changes.iter().map(|c| (c.fired, c.threshold)).collect::<Vec<_>>(), vec![(false, 85.0), (true, 80.0)]);
+        monitor.reload(vec![], secs(15));
+        assert_eq!(monitor.events[1].end, Some(secs(15)));
+    }
+
+    #[test]
+    fn test_known_metrics() {
+        for name in &["cpu_temp", "cpu12.freq", "disk.nvme0n1.latency", "pressure.cgroup_IO.full_avg60",
+                      "thermal.x86_pkg_temp_0", "thermal.acpitz.passive_margin", "hwmon.k10temp_Tctl"] {
+            assert!(is_known_metric(name), "{}", name);
+        }
+        for name in &["cpu_tmp", "disk.sda", "thermal.x86_pkg_temp 0", "net.eth0.rx_bytes", ""] {
+            assert!(!is_known_metric(name), "{}", name);
+        }
+        assert_eq!(metric_name("nvme@nvme1 Composite"), "nvme@nvme1_Composite");
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        let c = Condition::try_from("< 300 for 2m".to_string()).unwrap();
+        assert_eq!(c, Condition { op: Op::Below, threshold: 300.0, hold: Duration::from_secs(120) });
+        assert!(Condition::try_from("> -5.5".to_string()).is_ok());
+        for bad in &["= 85", "> hot", "> 85 for", "> 85 for 30h", "> 85 during 30s", "> 85 for 18446744073709551615m", ""] {
+            assert!(Condition::try_from(bad.to_string()).is_err(), "{}", bad);
+        }
+    }
+}
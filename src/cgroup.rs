@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::errors::*;
+use crate::parser::*;
+use crate::statistic::*;
+use crate::throttle::ThrottleEpisode;
+use crate::util::*;
+
+const CGROUP_DIR: &str = "/sys/fs/cgroup";
+// Where systemd mounts cgroup v2 in the hybrid layout
+const CGROUP_UNIFIED_DIR: &str = "/sys/fs/cgroup/unified";
+
+#[derive(Default)]
+struct CgroupCounters {
+    usage_usec: u64,
+    nr_periods: u64,
+    nr_throttled: u64,
+    throttled_usec: u64,
+    rbytes: u64,
+    wbytes: u64,
+    ios: u64,
+}
+
+pub struct CgroupStat {
+    dir: PathBuf,
+    counters: CgroupCounters,
+    last_time: Instant,
+}
+
+/// CPU usage is a percentage of one CPU, memory is in MiB, rates are per second.
+#[derive(PartialEq, Debug)]
+pub struct CgroupEntry {
+    pub usage: f64,
+    // Percentage of the CFS periods which were throttled, None without a CPU quota
+    pub throttled_periods: Option<f64>,
+    pub throttled_ms: f64,
+    // None if the memory controller is not enabled
+    pub memory: Option<f64>,
+    pub anon: f64,
+    pub file: f64,
+    pub read_mbps: f64,
+    pub write_mbps: f64,
+    pub iops: f64,
+}
+
+/// The directory of cgroup `name`, which is relative to the cgroup v2 mount.
+pub fn get_cgroup_path(name: &str) -> PathBuf {
+    let root = if !Path::new(CGROUP_DIR).join("cgroup.controllers").exists() && Path::new(CGROUP_UNIFIED_DIR).exists() {
+        CGROUP_UNIFIED_DIR
+    } else {
+        CGROUP_DIR
+    };
+    Path::new(root).join(name.trim_start_matches('/'))
+}
+
+fn read_key_values(path: &Path) -> Result<BTreeMap<String, u64>> {
+    parse_key_values(&path.to_string_lossy(), &fs::read_to_string(path)?)
+}
+
+fn read_counters(dir: &Path) -> Result<CgroupCounters> {
+    // The throttle counters only exist with the cpu controller enabled
+    let cpu = read_key_values(&dir.join("cpu.stat"))?;
+    let get = |k: &str| cpu.get(k).cloned().unwrap_or(0);
+    let mut result = CgroupCounters {
+        usage_usec: get("usage_usec"),
+        nr_periods: get("nr_periods"),
+        nr_throttled: get("nr_throttled"),
+        throttled_usec: get("throttled_usec"),
+        ..Default::default()
+    };
+    let io_file = dir.join("io.stat");
+    if let Ok(contents) = fs::read_to_string(&io_file) {
+        for io in parse_io_stat(&io_file.to_string_lossy(), &contents)? {
+            result.rbytes += io.rbytes;
+            result.wbytes += io.wbytes;
+            result.ios += io.rios + io.wios;
+        }
+    }
+    Ok(result)
+}
+
+fn get_cgroup_entry(old: &CgroupCounters, new: &CgroupCounters, memory: Option<u64>,
+                    memory_stat: &BTreeMap<String, u64>, secs: f64) -> CgroupEntry {
+    let rate = |new: u64, old: u64| new.saturating_sub(old) as f64 / secs;
+    let mib = |bytes: u64| bytes as f64 / 1024.0 / 1024.0;
+    let periods = new.nr_periods.saturating_sub(old.nr_periods);
+    CgroupEntry {
+        usage: rate(new.usage_usec, old.usage_usec) / 10_000.0,
+        throttled_periods: if periods == 0 {
+            None
+        } else {
+            Some(new.nr_throttled.saturating_sub(old.nr_throttled) as f64 / periods as f64 * 100.0)
+        },
+        throttled_ms: rate(new.throttled_usec, old.throttled_usec) / 1000.0,
+        memory: memory.map(mib),
+        anon: mib(memory_stat.get("anon").cloned().unwrap_or(0)),
+        file: mib(memory_stat.get("file").cloned().unwrap_or(0)),
+        read_mbps: mib(new.rbytes.saturating_sub(old.rbytes)) / secs,
+        write_mbps: mib(new.wbytes.saturating_sub(old.wbytes)) / secs,
+        iops: rate(new.ios, old.ios),
+    }
+}
+
+pub fn initial_cgroup_stat(dir: &Path) -> Result<CgroupStat> {
+    if !dir.join("cgroup.controllers").exists() {
+        bail!("{} is not a cgroup v2 directory.", dir.display());
+    }
+    Ok(CgroupStat { dir: dir.to_path_buf(), counters: read_counters(dir)?, last_time: Instant::now() })
+}
+
+pub fn collect_cgroup_info(stat: &mut CgroupStat) -> Result<CgroupEntry> {
+    let counters = read_counters(&stat.dir)?;
+    let memory = read_sysfs_u64(&stat.dir.join("memory.current")).ok();
+    let memory_stat = read_key_values(&stat.dir.join("memory.stat")).unwrap_or_default();
+    let now = Instant::now();
+    let secs = now.duration_since(stat.last_time).as_secs_f64();
+    let result = get_cgroup_entry(&stat.counters, &counters, memory, &memory_stat, secs);
+    stat.counters = counters;
+    stat.last_time = now;
+    Ok(result)
+}
+
+/// Every sample comes with its time, so that CFS throttling can be told
+/// apart from thermal throttling `episodes`.
+pub fn do_cgroup_statistic(name: &str, samples: &[(Duration, &CgroupEntry)], episodes: &[ThrottleEpisode]) -> String {
+    let title = format!("Cgroup {}:", name);
+    if samples.is_empty() {
+        return format!("{}no samples", title_tabs(&title));
+    }
+    let mut ret = vec![title];
+    let throttled: Vec<Duration> = samples.iter()
+        .filter(|(_, c)| c.throttled_periods.is_some_and(|p| p > 0.0))
+        .map(|(t, _)| *t)
+        .collect();
+    if samples.iter().all(|(_, c)| c.throttled_periods.is_none()) {
+        ret.push("CFS throttling:\t\tno CPU quota is set".to_string());
+    } else {
+        let hot = throttled.iter()
+            .filter(|t| episodes.iter().any(|e| **t >= e.start && **t <= e.start + e.duration))
+            .count();
+        let first = throttled.first().map(|t| format!(" | first at {}", format_elapsed(*t))).unwrap_or_default();
+        ret.push(format!("CFS throttling:\t\tthrottled in {} of {} samples, {} of them during thermal throttling{}",
+                         throttled.len(), samples.len(), hot, first));
+        let periods: Vec<f64> = samples.iter().filter_map(|(_, c)| c.throttled_periods).collect();
+        ret.push(format_stat("CFS throttled periods:", &periods,
+                             &[1.0, 10.0, 50.0], &["<1%", "1%-10%", "10%-50%", ">=50%"]));
+        let ms: Vec<f64> = samples.iter().map(|(_, c)| c.throttled_ms).collect();
+        ret.push(format_stat("CFS throttled ms/s:", &ms,
+                             &[1.0, 10.0, 100.0], &["<1", "1-10", "10-100", ">=100"]));
+    }
+    let values = |f: fn(&CgroupEntry) -> f64| -> Vec<f64> { samples.iter().map(|(_, c)| f(c)).collect() };
+    ret.push(format_stat("Cgroup CPU usage:", &values(|c| c.usage),
+                         &[10.0, 50.0, 90.0, 100.0], &["<10%", "10%-50%", "50%-90%", "90%-100%", ">=100%"]));
+    if samples.iter().any(|(_, c)| c.memory.is_some()) {
+        let memory: Vec<f64> = samples.iter().filter_map(|(_, c)| c.memory).collect();
+        ret.push(format_stat("Cgroup memory MiB:", &memory,
+                             &[64.0, 512.0, 4096.0], &["<64", "64-512", "512-4096", ">=4096"]));
+        ret.push(format_stat("Cgroup anon MiB:", &values(|c| c.anon),
+                             &[64.0, 512.0, 4096.0], &["<64", "64-512", "512-4096", ">=4096"]));
+        ret.push(format_stat("Cgroup file MiB:", &values(|c| c.file),
+                             &[64.0, 512.0, 4096.0], &["<64", "64-512", "512-4096", ">=4096"]));
+    }
+    ret.push(format_stat("Cgroup read MB/s:", &values(|c| c.read_mbps),
+                         &[1.0, 10.0, 100.0], &["<1", "1-10", "10-100", ">=100"]));
+    ret.push(format_stat("Cgroup write MB/s:", &values(|c| c.write_mbps),
+                         &[1.0, 10.0, 100.0], &["<1", "1-10", "10-100", ">=100"]));
+    ret.push(format_stat("Cgroup IOPS:", &values(|c| c.iops),
+                         &[10.0, 100.0, 1000.0], &["<10", "10-100", "100-1k", ">=1k"]));
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_cgroup(dir: &Path, usage: u64, periods: u64, throttled: u64, rbytes: u64) {
+        let cpu = format!("usage_usec {}\nuser_usec 0\nsystem_usec 0\nnr_periods {}\nnr_throttled {}\nthrottled_usec {}",
+                          usage, periods, throttled, throttled * 1000);
+        let io = format!("8:0 rbytes={} wbytes=0 rios=10 wios=0 dbytes=0 dios=0\n8:16 rbytes=0 wbytes=0 rios=0 wios={}",
+                         rbytes, periods);
+        write_attrs(dir, &[("cgroup.controllers", "cpu io memory"), ("cpu.stat", &cpu), ("io.stat", &io)]);
+    }
+
+    #[test]
+    fn test_collect_cgroup_info() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(initial_cgroup_stat(dir.path()).is_err());
+        write_cgroup(dir.path(), 1_000_000, 100, 0, 0);
+        let mut stat = initial_cgroup_stat(dir.path()).unwrap();
+
+        write_cgroup(dir.path(), 2_000_000, 150, 10, 4 * 1024 * 1024);
+        fs::write(dir.path().join("memory.current"), "104857600\n").unwrap();
+        fs::write(dir.path().join("memory.stat"), "anon 52428800\nfile 41943040\n").unwrap();
+        let counters = read_counters(dir.path()).unwrap();
+        let memory = read_sysfs_u64(&dir.path().join("memory.current")).ok();
+        let memory_stat = read_key_values(&dir.path().join("memory.stat")).unwrap();
+        let c = get_cgroup_entry(&stat.counters, &counters, memory, &memory_stat, 2.0);
+        assert_eq!(c, CgroupEntry {
+            usage: 50.0, throttled_periods: Some(20.0), throttled_ms: 5.0, memory: Some(100.0), anon: 50.0, file: 40.0,
+            read_mbps: 2.0, write_mbps: 0.0, iops: 25.0,
+        });
+        assert!(collect_cgroup_info(&mut stat).is_ok());
+
+        let episode = ThrottleEpisode {
+            start: Duration::from_secs(3), duration: Duration::from_secs(3), cores: vec![0], peak_temp: Some(96.0), confirmed: false,
+        };
+        let report = do_cgroup_statistic("bench.slice", &[(Duration::from_secs(3), &c), (Duration::from_secs(9), &c)], &[episode]);
+        assert!(report.contains("CFS throttling:\t\tthrottled in 2 of 2 samples, 1 of them during thermal throttling \
+                                 | first at +00:00:03"));
+        assert!(report.contains("Cgroup memory MiB:\tavg: 100.00"));
+    }
+}
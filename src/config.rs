@@ -0,0 +1,97 @@
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use crate::action::ActionConfig;
+use crate::alert::{is_known_metric, AlertRule};
+use crate::errors::*;
+use crate::fan::FanCurve;
+
+/// The config file given with --config, re-read on SIGHUP.
+#[derive(Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // Override --interval and --temp-limit, so that a reload can change them
+    pub interval: Option<u64>,
+    pub temp_limit: Option<f64>,
+    // Write the reports to this file instead of stdout
+    pub report_file: Option<String>,
+    #[serde(default, rename = "alert")]
+    pub alerts: Vec<AlertRule>,
+    // Run when an alert fires or clears
+    pub action: Option<ActionConfig>,
+    #[serde(default, rename = "fan")]
+    pub fans: Vec<FanCurve>,
+}
+
+pub fn parse_config(file: &str, contents: &str) -> Result<Config> {
+    let config: Config = toml::from_str(contents).chain_err(|| ErrorKind::LoadConfigFailed(file.to_string()))?;
+    if config.temp_limit.is_some_and(|t| !t.is_finite()) {
+        bail!("The temp_limit in {} is not a number.", file);
+    }
+    for a in &config.alerts {
+        if a.warn.is_none() && a.crit.is_none() {
+            bail!("Alert {} in {} needs a warn or a crit condition.", a.name(), file);
+        }
+        if !is_known_metric(&a.metric) {
+            bail!("Alert {} in {} has an unknown metric {}.", a.name(), file, a.metric);
+        }
+        if a.hysteresis < 0.0 {
+            bail!("Alert {} in {} has a negative hysteresis.", a.name(), file);
+        }
+    }
+    if let Some(a) = &config.action {
+        if a.command.is_none() && a.webhook.is_none() {
+            bail!("The action in {} needs a command or a webhook.", file);
+        }
+        if a.timeout == 0 {
+            bail!("The action timeout in {} must be at least 1 second.", file);
+        }
+    }
+    for f in &config.fans {
+        if let Some(e) = f.check() {
+            bail!("Fan {} in {} {}.", f.name(), file, e);
+        }
+    }
+    Ok(config)
+}
+
+pub fn load_config(path: &Path) -> Result<Config> {
+    let file = path.to_string_lossy();
+    let contents = fs::read_to_string(path).chain_err(|| ErrorKind::LoadConfigFailed(file.to_string()))?;
+    parse_config(&file, &contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = parse_config("pemon.toml", "[[alert]]\nmetric = \"cpu_temp\"\nwarn = \"> 85 for 30s\"\n\
+                                                 crit = \"> 95 for 5s\"\nhysteresis = 3.0\n\n\
+                                                 [[alert]]\nname = \"CPU fan stalled\"\nmetric = \"cpu_fan_rpm\"\n\
+                                                 crit = \"< 300 for 5s\"\n").unwrap();
+        assert_eq!(config.alerts.len(), 2);
+        assert_eq!(config.alerts[0].name(), "cpu_temp");
+        assert_eq!(config.alerts[1].name(), "CPU fan stalled");
+        let config = parse_config("pemon.toml", "[action]\ncommand = \"notify-send pemon\"\n").unwrap();
+        assert_eq!(config.action, Some(ActionConfig {
+            command: Some("notify-send pemon".to_string()), webhook: None, timeout: 10, retries: 2,
+        }));
+        assert_eq!(parse_config("pemon.toml", "").unwrap(), Config::default());
+        let config = parse_config("pemon.toml", "interval = 10\ntemp_limit = 90.5\nreport_file = \"/run/pemon.report\"\n").unwrap();
+        assert_eq!((config.interval, config.temp_limit, config.report_file.as_deref()),
+                   (Some(10), Some(90.5), Some("/run/pemon.report")));
+
+        let e = parse_config("pemon.toml", "[[alert]]\nmetric = \"cpu_temp\"\nwarn = \"85\"\n").unwrap_err();
+        assert!(e.iter().any(|e| e.to_string().contains("must start with '>' or '<'")));
+        assert!(parse_config("pemon.toml", "[[alert]]\nmetric = \"cpu_temp\"\n").is_err());
+        assert!(parse_config("pemon.toml", "[[alert]]\nmetric = \"cpu_temp\"\nwarn = \"> 1\"\nhysteresis = -1.0\n").is_err());
+        assert!(parse_config("pemon.toml", "[[alarm]]\n").is_err());
+        let e = parse_config("pemon.toml", "[[alert]]\nmetric = \"cpu_tmp\"\nwarn = \"> 85\"\n").unwrap_err();
+        assert_eq!(e.to_string(), "Alert cpu_tmp in pemon.toml has an unknown metric cpu_tmp.");
+        assert!(parse_config("pemon.toml", "temp_limit = nan\n").is_err());
+        assert!(parse_config("pemon.toml", "[action]\ntimeout = 5\n").is_err());
+        assert!(parse_config("pemon.toml", "[[fan]]\nchip = \"nct6798\"\npwm = \"pwm2\"\ncurve = []\n").is_err());
+    }
+}
@@ -1,167 +1,106 @@
-use std::io::prelude::*;
-use std::fs::File;
+use std::fs;
+use std::collections::BTreeMap;
+use std::time::Duration;
 use crate::errors::*;
+use crate::cpuidle::IdleStateEntry;
+use crate::util::*;
+use crate::parser::*;
 
-const CPU_FREQ_FILE:&'static str = "/proc/cpuinfo";
-const CPU_STAT_FILE:&'static str = "/proc/stat";
+const CPU_FREQ_FILE: &str = "/proc/cpuinfo";
+const CPU_STAT_FILE: &str = "/proc/stat";
+const CPU_ONLINE_FILE: &str = "/sys/devices/system/cpu/online";
+const CPU_PRESENT_FILE: &str = "/sys/devices/system/cpu/present";
 
-#[derive(PartialEq, Debug)]
-pub struct CpuStat {
-    total: usize,
-    idle: usize,
-}
-
-impl CpuStat {
-    fn new() -> CpuStat {
-        CpuStat {
-            total: 0,
-            idle: 0,
-        }
-    }
-}
+pub use crate::parser::CpuStat;
 
 #[derive(PartialEq, Debug)]
 pub struct CpuInfoEntry {
     pub id: usize,
     pub freq: f64,
     pub usage: f64,
+    pub idle_states: Vec<IdleStateEntry>,
 }
 
-pub fn collect_cpu_info(cpu_stats: &mut Vec<CpuStat>) -> Result<Vec<CpuInfoEntry>> {
-    let mut result = Vec::new();
-    let mut f = File::open(CPU_FREQ_FILE)?;
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)?;
-
-    let mut id = 1;
-    for l in contents.lines() {
-        let line = l.trim().to_string();
-        if line.len() == 0 {
-            continue;
-        }
-
-        if line.starts_with("cpu MHz") {
-            if let Some(pos) = line.find(':') {
-                let sfreq = line[(pos + 1)..].trim().to_string();
-                if let Ok(freq) = sfreq.parse::<f64>() {
-                    let cfe = CpuInfoEntry {
-                        id: id,
-                        freq: freq,
-                        usage: 0.0,
-                    };
-                    result.push(cfe);
-                    id += 1;
-                } else {
-                    warn!("Illegal cpuinfo line is found: {}", line);
-                    bail!(ErrorKind::InvalidCpuFreqLine);
-                }
-            } else {
-                warn!("Illegal cpuinfo line is found: {}", line);
-                bail!(ErrorKind::InvalidCpuFreqLine);
-            }
-        }
-    }
+/// Get the ids of the online CPUs.
+pub fn get_online_cpus() -> Result<Vec<usize>> {
+    let contents = fs::read_to_string(CPU_ONLINE_FILE)?;
+    parse_cpu_list(CPU_ONLINE_FILE, &contents)
+}
 
-    let mut f = File::open(CPU_STAT_FILE)?;
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)?;
-    for i in 0..result.len() {
-        let name = format!("cpu{}", i);
-        let mut cs = CpuStat::new();
-        for ref l in contents.lines() {
-            let line = l.trim().to_string();
-            if line.len() == 0 {
-                continue;
-            }
-
-            if line.starts_with(name.as_str()) {
-                let v: Vec<&str> = line[(name.len() + 1)..].split(' ').collect();
-                let mut total = 0;
-                for i in 0..v.len() {
-                    total += v[i].parse::<usize>().unwrap();
-                }
-                // The 4th member is idle jiffies, kernel won't break userspace
-                let idle = v[3].parse::<usize>().unwrap();
-
-                cs = CpuStat {
-                    total: total,
-                    idle: idle,
-                };
-                break;
-            }
-        }
+/// Get the ids of all CPUs which can be brought online.
+pub fn get_present_cpus() -> Result<Vec<usize>> {
+    let contents = fs::read_to_string(CPU_PRESENT_FILE)?;
+    parse_cpu_list(CPU_PRESENT_FILE, &contents)
+}
 
-        if cs.total == 0 {
-            bail!(ErrorKind::CpuStatNotFound);
-        }
-        let new_stat = cs;
-        let old_stat = &cpu_stats[i];
-        let total = new_stat.total - old_stat.total;
-        let idle = new_stat.idle - old_stat.idle;
-        result[i].usage = 100.0 * (total - idle) as f64 / total as f64;
-        cpu_stats[i] = new_stat;
+/// Collect frequency and usage of the online CPUs. CPUs which went offline
+/// are dropped from `cpu_stats`, CPUs which came online are added to it and
+/// show up from the next collection on.
+pub fn collect_cpu_info(cpu_stats: &mut BTreeMap<usize, CpuStat>) -> Result<Vec<CpuInfoEntry>> {
+    let mut result = Vec::new();
+    let online = get_online_cpus()?;
+    let freqs = parse_cpuinfo(CPU_FREQ_FILE, &fs::read_to_string(CPU_FREQ_FILE)?)?;
+    let mut new_stats = parse_proc_stat(CPU_STAT_FILE, &fs::read_to_string(CPU_STAT_FILE)?)?.cpus;
+    // A CPU may change its state between reading the files
+    new_stats.retain(|id, _| online.contains(id));
+
+    for (id, new_stat) in &new_stats {
+        let old_stat = match cpu_stats.get(id) {
+            Some(o) => o,
+            None => continue,
+        };
+        let total = new_stat.total.saturating_sub(old_stat.total);
+        let idle = new_stat.idle.saturating_sub(old_stat.idle);
+        let usage = if total == 0 { 0.0 } else { 100.0 * total.saturating_sub(idle) as f64 / total as f64 };
+        result.push(CpuInfoEntry {
+            id: *id,
+            freq: freqs.get(id).cloned().unwrap_or(0.0),
+            usage,
+            idle_states: Vec::new(),
+        });
     }
+    *cpu_stats = new_stats;
 
     Ok(result)
 }
 
-pub fn get_cpu_num() -> Result<usize> {
-    let mut f = File::open(CPU_FREQ_FILE)?;
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)?;
+pub fn initial_cpu_stats() -> Result<BTreeMap<usize, CpuStat>> {
+    let online = get_online_cpus()?;
+    let mut result = parse_proc_stat(CPU_STAT_FILE, &fs::read_to_string(CPU_STAT_FILE)?)?.cpus;
+    result.retain(|id, _| online.contains(id));
+    if result.is_empty() {
+        bail!(ErrorKind::CpuStatNotFound);
+    }
+    Ok(result)
+}
 
-    let mut count = 0;
-    for l in contents.lines() {
-        let line = l.trim().to_string();
-        if line.len() == 0 {
-            continue;
-        }
+/// A CPU went offline or came online during the run.
+#[derive(PartialEq, Debug)]
+pub struct CpuHotplugEvent {
+    pub time: Duration,
+    pub id: usize,
+    pub online: bool,
+}
 
-        if line.starts_with("processor") {
-            count += 1;
-        }
+/// Compare the CPUs of two collections.
+pub fn get_cpu_hotplug_events(time: Duration, old: &[usize], new: &[usize]) -> Vec<CpuHotplugEvent> {
+    let mut result = Vec::new();
+    for id in old.iter().filter(|id| !new.contains(id)) {
+        result.push(CpuHotplugEvent { time, id: *id, online: false });
     }
-    Ok(count)
+    for id in new.iter().filter(|id| !old.contains(id)) {
+        result.push(CpuHotplugEvent { time, id: *id, online: true });
+    }
+    result
 }
 
-pub fn initial_cpu_stats(cpu_num: usize) -> Result<Vec<CpuStat>> {
-    let mut result: Vec<CpuStat> = Vec::new();
-    let mut f = File::open(CPU_STAT_FILE)?;
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)?;
-
-    for i in 0..cpu_num {
-        let name = format!("cpu{}", i);
-        let mut cs = CpuStat::new();
-        for ref l in contents.lines() {
-            let line = l.trim().to_string();
-            if line.len() == 0 {
-                continue;
-            }
-
-            if line.starts_with(name.as_str()) {
-                let v: Vec<&str> = line[(name.len() + 1)..].split(' ').collect();
-                let mut total = 0;
-                for i in 0..v.len() {
-                    total += v[i].parse::<usize>().unwrap();
-                }
-                let idle = v[3].parse::<usize>().unwrap();
-
-                cs = CpuStat {
-                    total: total,
-                    idle: idle,
-                };
-                break;
-            }
-        }
-
-        if cs.total == 0 {
-            bail!(ErrorKind::CpuStatNotFound);
-        } else {
-            result.push(cs);
-        }
+pub fn do_cpu_hotplug_statistic(events: &[&CpuHotplugEvent]) -> String {
+    let mut ret = format!("CPU hotplug:\t\t{} event(s)", events.len());
+    for e in events {
+        ret = format!("{}\nCPU{:02} {}:\t\tat: {}", ret, e.id,
+                      if e.online { "online" } else { "offline" }, format_elapsed(e.time));
     }
-    Ok(result)
+    ret
 }
 
 #[cfg(test)]
@@ -170,22 +109,32 @@ mod tests {
     use std::thread;
 
     #[test]
-    fn test_get_cpu_num() {
-        let result = get_cpu_num().unwrap();
-        assert_eq!(result, 16);
+    fn test_get_online_cpus() {
+        let result = get_online_cpus().unwrap();
+        assert_eq!(result.len(), 16);
     }
 
     #[test]
     fn test_collect_cpu_info() {
-        let mut stats = initial_cpu_stats(get_cpu_num().unwrap()).unwrap();
+        let mut stats = initial_cpu_stats().unwrap();
         thread::sleep(std::time::Duration::from_secs(2));
         let result = collect_cpu_info(&mut stats).unwrap();
+        let online = get_online_cpus().unwrap();
         for i in 0..result.len() {
             let cie = &result[i];
-            assert_eq!(cie.id, i+1);
+            assert_eq!(cie.id, online[i]);
             assert_eq!(cie.freq > 0.0, true );
             assert_eq!(cie.usage >= 0.0, true );
             println!("CPU {} freq: {}, usage: {}", cie.id, cie.freq, cie.usage);
         }
     }
+
+    #[test]
+    fn test_get_cpu_hotplug_events() {
+        let events = get_cpu_hotplug_events(Duration::from_secs(6), &[0, 1, 2], &[0, 2, 3]);
+        assert_eq!(events, vec![
+            CpuHotplugEvent { time: Duration::from_secs(6), id: 1, online: false },
+            CpuHotplugEvent { time: Duration::from_secs(6), id: 3, online: true },
+        ]);
+    }
 }
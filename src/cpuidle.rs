@@ -0,0 +1,197 @@
+use std::fs;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::errors::*;
+use crate::cpu::CpuInfoEntry;
+use crate::util::*;
+
+const CPU_SYS_DIR: &str = "/sys/devices/system/cpu";
+
+struct IdleCounter {
+    path: PathBuf,
+    name: String,
+    time: u64,
+    usage: u64,
+}
+
+pub struct CpuIdleStat {
+    // Idle state counters of every CPU, keyed by CPU id
+    cpus: BTreeMap<usize, Vec<IdleCounter>>,
+    root: PathBuf,
+    last_time: Instant,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct IdleStateEntry {
+    pub name: String,
+    // Percentage of the interval spent in this state
+    pub residency: f64,
+    // Entries into this state per second
+    pub transitions: f64,
+}
+
+fn read_idle_counters(root: &Path, cpu: usize) -> Result<Vec<IdleCounter>> {
+    let mut counters = Vec::new();
+    let dir = root.join(format!("cpu{}/cpuidle", cpu));
+    // cpuidle may be disabled, e.g. with "cpuidle.off=1" or in a VM
+    for m in 0.. {
+        let path = dir.join(format!("state{}", m));
+        if !path.exists() {
+            break;
+        }
+        counters.push(IdleCounter {
+            name: fs::read_to_string(path.join("name"))?.trim().to_string(),
+            time: read_sysfs_u64(&path.join("time"))?,
+            usage: read_sysfs_u64(&path.join("usage"))?,
+            path,
+        });
+    }
+    Ok(counters)
+}
+
+fn initial_cpu_idle_stat_from(root: &Path, cpus: &[usize]) -> Result<CpuIdleStat> {
+    let mut result = BTreeMap::new();
+    for &cpu in cpus {
+        result.insert(cpu, read_idle_counters(root, cpu)?);
+    }
+    Ok(CpuIdleStat { cpus: result, root: root.to_path_buf(), last_time: Instant::now() })
+}
+
+fn read_idle_states(counters: &mut [IdleCounter], secs: f64) -> Result<Vec<IdleStateEntry>> {
+    let mut states = Vec::new();
+    for c in counters.iter_mut() {
+        let time = read_sysfs_u64(&c.path.join("time"))?;
+        let usage = read_sysfs_u64(&c.path.join("usage"))?;
+        // "time" is in microseconds
+        states.push(IdleStateEntry {
+            name: c.name.clone(),
+            residency: time.saturating_sub(c.time) as f64 / 10_000.0 / secs,
+            transitions: usage.saturating_sub(c.usage) as f64 / secs,
+        });
+        c.time = time;
+        c.usage = usage;
+    }
+    Ok(states)
+}
+
+fn collect_cpu_idle_info_from(stat: &mut CpuIdleStat, cpu_info: &mut [CpuInfoEntry], now: Instant) -> Result<()> {
+    let secs = now.duration_since(stat.last_time).as_secs_f64();
+    // Forget offline CPUs, so their counters restart when they come back
+    stat.cpus.retain(|id, _| cpu_info.iter().any(|c| c.id == *id));
+    for cie in cpu_info.iter_mut() {
+        // A CPU which goes offline after /proc/stat was read loses its cpuidle
+        // dir, it's left without idle states and forgotten
+        let result = match stat.cpus.get_mut(&cie.id) {
+            Some(counters) => read_idle_states(counters, secs).map(|s| cie.idle_states = s),
+            None => read_idle_counters(&stat.root, cie.id).map(|c| { stat.cpus.insert(cie.id, c); }),
+        };
+        if let Err(e) = result {
+            debug!("Skip the idle states of CPU{:02}: {}", cie.id, e);
+            stat.cpus.remove(&cie.id);
+        }
+    }
+    stat.last_time = now;
+    Ok(())
+}
+
+/// Record the current idle state counters of every CPU.
+pub fn initial_cpu_idle_stat(cpus: &[usize]) -> Result<CpuIdleStat> {
+    initial_cpu_idle_stat_from(Path::new(CPU_SYS_DIR), cpus)
+}
+
+/// Fill in the idle state residency of every CPU since the last call.
+pub fn collect_cpu_idle_info(stat: &mut CpuIdleStat, cpu_info: &mut [CpuInfoEntry]) -> Result<()> {
+    collect_cpu_idle_info_from(stat, cpu_info, Instant::now())
+}
+
+fn idle_line(title: &str, states: &[IdleStateEntry], len: usize) -> String {
+    let len = len.max(1);
+    let v: Vec<String> = states.iter()
+        .map(|s| format!("{}: {:.2}% {:.1}/s", s.name, s.residency / len as f64, s.transitions / len as f64))
+        .collect();
+    format!("{}{}", title, v.join(" | "))
+}
+
+// Sum residency and transitions per state name, keeping the sysfs order
+fn accumulate(acc: &mut Vec<IdleStateEntry>, states: &[IdleStateEntry]) {
+    for s in states {
+        match acc.iter_mut().find(|a| a.name == s.name) {
+            Some(a) => {
+                a.residency += s.residency;
+                a.transitions += s.transitions;
+            },
+            None => acc.push(s.clone()),
+        }
+    }
+}
+
+pub fn do_cpu_idle_statistic(samples: &[&[CpuInfoEntry]]) -> String {
+    // Only count the samples which carry idle states of each CPU, which the
+    // first sample after it comes online doesn't
+    let mut cpus: BTreeMap<usize, (Vec<IdleStateEntry>, usize)> = BTreeMap::new();
+    for s in samples {
+        for cie in s.iter() {
+            let (acc, len) = cpus.entry(cie.id).or_insert((Vec::new(), 0));
+            if !cie.idle_states.is_empty() {
+                accumulate(acc, &cie.idle_states);
+                *len += 1;
+            }
+        }
+    }
+    if cpus.values().all(|(acc, _)| acc.is_empty()) {
+        return "CPU idle states:\tcpuidle info is unavailable".to_string();
+    }
+    let mut ret = Vec::new();
+    let mut all = Vec::new();
+    let mut all_len = 0;
+    for (id, (acc, len)) in &cpus {
+        ret.push(idle_line(&format!("CPU{:02} idle states:\t", id), acc, *len));
+        accumulate(&mut all, acc);
+        all_len += len;
+    }
+    ret.push(idle_line("All CPUs idle states:\t", &all, all_len));
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn write_state(root: &Path, cpu: usize, m: usize, name: &str, time: u64, usage: u64) {
+        write_attrs(&root.join(format!("cpu{}/cpuidle/state{}", cpu, m)),
+                    &[("name", name), ("time", &time.to_string()), ("usage", &usage.to_string())]);
+    }
+
+    #[test]
+    fn test_collect_cpu_idle_info() {
+        let dir = tempfile::tempdir().unwrap();
+        write_state(dir.path(), 0, 0, "POLL", 0, 0);
+        write_state(dir.path(), 0, 1, "C2", 1_000_000, 100);
+        let mut stat = initial_cpu_idle_stat_from(dir.path(), &[0]).unwrap();
+
+        write_state(dir.path(), 0, 1, "C2", 2_500_000, 400);
+        let mut cpu_info = vec![CpuInfoEntry { id: 0, freq: 3600.0, usage: 25.0, idle_states: Vec::new() }];
+        let now = stat.last_time + Duration::from_secs(2);
+        collect_cpu_idle_info_from(&mut stat, &mut cpu_info, now).unwrap();
+        assert_eq!(cpu_info[0].idle_states, vec![
+            IdleStateEntry { name: "POLL".to_string(), residency: 0.0, transitions: 0.0 },
+            IdleStateEntry { name: "C2".to_string(), residency: 75.0, transitions: 150.0 },
+        ]);
+
+        // Just online, so without idle states yet
+        // CPU0 goes offline between reading /proc/stat and cpuidle
+        fs::remove_dir_all(dir.path().join("cpu0/cpuidle")).unwrap();
+        let mut offline = vec![CpuInfoEntry { id: 0, freq: 3600.0, usage: 25.0, idle_states: Vec::new() }];
+        collect_cpu_idle_info_from(&mut stat, &mut offline, now + Duration::from_secs(2)).unwrap();
+        assert!(offline[0].idle_states.is_empty());
+        assert!(stat.cpus.is_empty());
+
+        let online = [CpuInfoEntry { id: 0, freq: 3600.0, usage: 25.0, idle_states: Vec::new() }];
+        let samples = [&online[..], &cpu_info[..], &cpu_info[..]];
+        assert_eq!(do_cpu_idle_statistic(&samples),
+                   "CPU00 idle states:\tPOLL: 0.00% 0.0/s | C2: 75.00% 150.0/s\n\
+                    All CPUs idle states:\tPOLL: 0.00% 0.0/s | C2: 75.00% 150.0/s");
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+use crate::errors::*;
+use crate::parser::*;
+use crate::statistic::*;
+
+const DISKSTATS_FILE: &str = "/proc/diskstats";
+// Only whole disks have an entry here
+const BLOCK_SYS_DIR: &str = "/sys/block";
+const SECTOR_SIZE: f64 = 512.0;
+
+pub struct DiskIoStat {
+    disks: BTreeMap<String, DiskStat>,
+    partitions: bool,
+    last_time: Instant,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct DiskEntry {
+    pub name: String,
+    pub read_mbps: f64,
+    pub write_mbps: f64,
+    pub iops: f64,
+    // Average time (ms) a request took, None if there was no request
+    pub latency: Option<f64>,
+    // Percentage of time the device was busy
+    pub util: f64,
+}
+
+fn read_diskstats(partitions: bool) -> Result<BTreeMap<String, DiskStat>> {
+    let stats = parse_diskstats(DISKSTATS_FILE, &fs::read_to_string(DISKSTATS_FILE)?)?;
+    Ok(stats.into_iter()
+        .filter(|d| partitions || Path::new(BLOCK_SYS_DIR).join(&d.name).exists())
+        .map(|d| (d.name.clone(), d))
+        .collect())
+}
+
+fn get_disk_entry(old: &DiskStat, new: &DiskStat, secs: f64) -> DiskEntry {
+    let ios = new.reads.saturating_sub(old.reads) + new.writes.saturating_sub(old.writes);
+    let ticks = new.read_ticks.saturating_sub(old.read_ticks) + new.write_ticks.saturating_sub(old.write_ticks);
+    let mb = |new: u64, old: u64| new.saturating_sub(old) as f64 * SECTOR_SIZE / 1024.0 / 1024.0;
+    DiskEntry {
+        name: new.name.clone(),
+        read_mbps: mb(new.sectors_read, old.sectors_read) / secs,
+        write_mbps: mb(new.sectors_written, old.sectors_written) / secs,
+        iops: ios as f64 / secs,
+        latency: if ios == 0 { None } else { Some(ticks as f64 / ios as f64) },
+        util: (new.io_ticks.saturating_sub(old.io_ticks) as f64 / 10.0 / secs).min(100.0),
+    }
+}
+
+/// Record the current counters of every disk. Partitions are skipped
+/// unless `partitions` is set.
+pub fn initial_disk_io_stat(partitions: bool) -> Result<DiskIoStat> {
+    Ok(DiskIoStat {
+        disks: read_diskstats(partitions)?,
+        partitions,
+        last_time: Instant::now(),
+    })
+}
+
+pub fn collect_disk_io_info(stat: &mut DiskIoStat) -> Result<Vec<DiskEntry>> {
+    let disks = read_diskstats(stat.partitions)?;
+    let now = Instant::now();
+    let secs = now.duration_since(stat.last_time).as_secs_f64();
+    let mut result = Vec::new();
+    for (name, new) in &disks {
+        // A newly appeared disk is reported from the next collection on
+        if let Some(old) = stat.disks.get(name) {
+            result.push(get_disk_entry(old, new, secs));
+        }
+    }
+    stat.disks = disks;
+    stat.last_time = now;
+    Ok(result)
+}
+
+pub fn do_disk_io_statistic(samples: &[&[DiskEntry]]) -> String {
+    let mut disks: BTreeMap<&str, Vec<&DiskEntry>> = BTreeMap::new();
+    for s in samples {
+        for d in s.iter() {
+            disks.entry(&d.name).or_default().push(d);
+        }
+    }
+    let mut ret = Vec::new();
+    for (name, entries) in disks {
+        // Loop devices and unused disks would only add noise
+        if entries.iter().all(|d| d.iops == 0.0) {
+            continue;
+        }
+        let values = |f: fn(&DiskEntry) -> f64| -> Vec<f64> { entries.iter().map(|d| f(d)).collect() };
+        ret.push(format_stat(&format!("{} read MB/s:", name), &values(|d| d.read_mbps),
+                             &[1.0, 10.0, 100.0, 500.0], &["<1", "1-10", "10-100", "100-500", ">=500"]));
+        ret.push(format_stat(&format!("{} write MB/s:", name), &values(|d| d.write_mbps),
+                             &[1.0, 10.0, 100.0, 500.0], &["<1", "1-10", "10-100", "100-500", ">=500"]));
+        ret.push(format_stat(&format!("{} IOPS:", name), &values(|d| d.iops),
+                             &[10.0, 100.0, 1000.0, 10000.0], &["<10", "10-100", "100-1k", "1k-10k", ">=10k"]));
+        let latency: Vec<f64> = entries.iter().filter_map(|d| d.latency).collect();
+        ret.push(format_stat(&format!("{} latency ms:", name), &latency,
+                             &[1.0, 5.0, 20.0], &["<1", "1-5", "5-20", ">=20"]));
+        ret.push(format_stat(&format!("{} util:", name), &values(|d| d.util),
+                             &[10.0, 50.0, 90.0], &["<10%", "10%-50%", "50%-90%", ">=90%"]));
+    }
+    if ret.is_empty() {
+        return "Disk I/O:\t\tno disk I/O during the run".to_string();
+    }
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_disk_entry() {
+        let old = parse_diskstats(DISKSTATS_FILE, "259 0 nvme0n1 1000 0 80000 300 500 0 40000 200 0 450 500\n").unwrap();
+        let new = parse_diskstats(DISKSTATS_FILE, "259 0 nvme0n1 1400 0 100480 700 700 0 81000 400 0 1950 500\n").unwrap();
+        let d = get_disk_entry(&old[0], &new[0], 2.0);
+        assert_eq!(d.read_mbps, 5.0);
+        assert_eq!(d.write_mbps, 10.009765625);
+        assert_eq!(d.iops, 300.0);
+        assert_eq!(d.latency, Some(1.0));
+        assert_eq!(d.util, 75.0);
+
+        let idle = get_disk_entry(&new[0], &new[0], 2.0);
+        assert_eq!(idle.latency, None);
+        assert_eq!(do_disk_io_statistic(&[&[idle]]), "Disk I/O:\t\tno disk I/O during the run");
+    }
+}
@@ -37,6 +37,7 @@ error_chain! {
     foreign_links {
         Io(::std::io::Error);
         ParseInt(::std::num::ParseIntError);
+        Toml(::toml::de::Error);
     }
 
     // Define additional `ErrorKind` variants. The syntax here is
@@ -58,5 +59,14 @@ error_chain! {
         GetRpmFailed {
             display("Get sensor rpm info failed.")
         }
+        ParseFailed(file: String, lineno: usize, line: String, reason: String) {
+            display("Failed to parse {} line {} '{}': {}", file, lineno, line, reason)
+        }
+        LoadProfileFailed(file: String) {
+            display("Load board profile {} failed.", file)
+        }
+        LoadConfigFailed(file: String) {
+            display("Load config file {} failed.", file)
+        }
     }
 }
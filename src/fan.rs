@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::errors::*;
+use crate::hwmon::*;
+use crate::statistic::*;
+use crate::util::*;
+
+// pwmN takes a duty cycle of 0-255
+const PWM_MAX: f64 = 255.0;
+// pwmN_enable value for manual control
+const PWM_MANUAL: u64 = 1;
+
+fn default_metric() -> String { "cpu_temp".to_string() }
+fn default_max() -> f64 { 100.0 }
+
+/// Drive one pwm channel from a curve of (temperature, duty %) points,
+/// which is linear between the points and flat beyond them.
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FanCurve {
+    // hwmon name, may contain wildcards
+    pub chip: String,
+    // e.g. "pwm2"
+    pub pwm: String,
+    #[serde(default = "default_metric")]
+    pub metric: String,
+    pub curve: Vec<(f64, f64)>,
+    // Duty % clamps
+    #[serde(default)]
+    pub min: f64,
+    #[serde(default = "default_max")]
+    pub max: f64,
+    // Share of the previous duty kept in the next one, 0 for no smoothing
+    #[serde(default)]
+    pub smoothing: f64,
+    // Duty % for one sample when a stopped fan has to start
+    pub spin_up: Option<f64>,
+}
+
+impl FanCurve {
+    pub fn name(&self) -> String {
+        format!("{} {}", self.chip, self.pwm)
+    }
+
+    /// Why the curve can't be used, None if it can.
+    pub fn check(&self) -> Option<String> {
+        let duty = |d: f64| (0.0..=100.0).contains(&d);
+        if self.curve.is_empty() {
+            Some("has no curve point".to_string())
+        } else if self.curve.windows(2).any(|w| w[0].0 >= w[1].0) {
+            Some("needs curve points in ascending temperatures".to_string())
+        } else if !self.curve.iter().all(|(_, d)| duty(*d)) || !duty(self.min) || !duty(self.max) || self.min > self.max
+            || !self.spin_up.is_none_or(duty) {
+            Some("has a duty outside 0-100% or min above max".to_string())
+        } else if !(0.0..1.0).contains(&self.smoothing) {
+            Some("needs a smoothing in [0, 1)".to_string())
+        } else {
+            None
+        }
+    }
+
+    fn duty(&self, temp: f64) -> f64 {
+        let first = self.curve[0];
+        let last = self.curve[self.curve.len() - 1];
+        if temp <= first.0 {
+            return first.1;
+        }
+        if temp >= last.0 {
+            return last.1;
+        }
+        let w = self.curve.windows(2).find(|w| temp < w[1].0).unwrap_or(&self.curve[..2]);
+        let ((t0, d0), (t1, d1)) = (w[0], w[1]);
+        d0 + (d1 - d0) * (temp - t0) / (t1 - t0)
+    }
+}
+
+struct FanChannel {
+    curve: FanCurve,
+    pwm: PathBuf,
+    enable: PathBuf,
+    // Restored when the controller is dropped
+    orig_enable: u64,
+    orig_pwm: u64,
+    // Last smoothed duty %
+    duty: Option<f64>,
+    stopped: bool,
+    missing: bool,
+    duties: Vec<f64>,
+}
+
+/// Writes the pwm channels, and puts them back to their original mode when
+/// dropped, which happens on exit and on a panic.
+pub struct FanController {
+    channels: Vec<FanChannel>,
+    restored: bool,
+}
+
+fn initial_fan_controller_from(root: &Path, curves: &[FanCurve],
+                               metrics: &BTreeMap<String, f64>) -> Result<FanController> {
+    if let Some(c) = curves.iter().find(|c| !metrics.contains_key(&c.metric)) {
+        bail!("Fan {}: no sensor backs metric {}.", c.name(), c.metric);
+    }
+    let chips = find_chips(root);
+    let mut controller = FanController { channels: Vec::new(), restored: false };
+    for c in curves {
+        let dir = match chips.iter().find(|(name, dir)| wildcard_match(&c.chip, name) && dir.join(&c.pwm).exists()) {
+            Some((_, dir)) => dir,
+            None => bail!("Fan {} is not found.", c.name()),
+        };
+        let pwm = dir.join(&c.pwm);
+        let enable = dir.join(format!("{}_enable", c.pwm));
+        let orig_pwm = read_sysfs_u64(&pwm)?;
+        let orig_enable = read_sysfs_u64(&enable)?;
+        // Dropping the controller on an error restores the channels already taken over
+        write_sysfs(&enable, &PWM_MANUAL.to_string())?;
+        info!("Fan {} is under control, its pwm_enable was {}.", c.name(), orig_enable);
+        controller.channels.push(FanChannel {
+            curve: c.clone(), pwm, enable, orig_enable, orig_pwm,
+            duty: None, stopped: orig_pwm == 0, missing: false, duties: Vec::new(),
+        });
+    }
+    Ok(controller)
+}
+
+/// Take over the pwm channels of `curves`, whose metrics have to be among
+/// `metrics`, the sensor metrics read at start.
+pub fn initial_fan_controller(curves: &[FanCurve], metrics: &BTreeMap<String, f64>) -> Result<FanController> {
+    initial_fan_controller_from(Path::new(HWMON_SYS_DIR), curves, metrics)
+}
+
+impl FanController {
+    /// Write the duties for the metrics of one sample.
+    pub fn update(&mut self, metrics: &BTreeMap<String, f64>) -> Result<()> {
+        for ch in self.channels.iter_mut() {
+            let c = &ch.curve;
+            let duty = match (metrics.get(&c.metric), ch.duty) {
+                (Some(t), Some(last)) => last * c.smoothing + c.duty(*t) * (1.0 - c.smoothing),
+                (Some(t), None) => c.duty(*t),
+                // Fail safe: max duty right away, without smoothing towards it
+                (None, _) => {
+                    if !ch.missing {
+                        warn!("Fan {}: metric {} is not available, run at max duty.", c.name(), c.metric);
+                    }
+                    c.max
+                },
+            }.max(c.min).min(c.max);
+            ch.missing = !metrics.contains_key(&c.metric);
+            let mut written = duty;
+            if ch.stopped && duty > 0.0 {
+                written = duty.max(c.spin_up.unwrap_or(0.0));
+            }
+            write_sysfs(&ch.pwm, &((written / 100.0 * PWM_MAX).round() as u64).to_string())?;
+            ch.duty = Some(duty);
+            ch.stopped = written == 0.0;
+            ch.duties.push(written);
+        }
+        Ok(())
+    }
+
+    /// Forget the duties written so far.
+    pub fn reset(&mut self) {
+        for ch in self.channels.iter_mut() {
+            ch.duties.clear();
+        }
+    }
+
+    /// Put the channels back to their original mode, and duty if it was manual.
+    pub fn restore(&mut self) {
+        if self.restored {
+            return;
+        }
+        self.restored = true;
+        for ch in &self.channels {
+            let mut result = write_sysfs(&ch.enable, &ch.orig_enable.to_string());
+            if result.is_ok() && ch.orig_enable == PWM_MANUAL {
+                result = write_sysfs(&ch.pwm, &ch.orig_pwm.to_string());
+            }
+            match result {
+                Ok(()) => info!("Fan {} is restored, pwm_enable: {}.", ch.curve.name(), ch.orig_enable),
+                Err(e) => error!("Restore fan {} failed: {}", ch.curve.name(), e),
+            }
+        }
+    }
+}
+
+impl Drop for FanController {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+pub fn do_fan_statistic(controller: &FanController) -> String {
+    controller.channels.iter()
+        .map(|ch| format_stat(&format!("{} duty %:", ch.curve.name()), &ch.duties,
+                              &[30.0, 50.0, 70.0, 90.0], &["<30%", "30%-50%", "50%-70%", "70%-90%", ">=90%"]))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn test_fan_controller() {
+        let dir = tempfile::tempdir().unwrap();
+        let chip = dir.path().join("hwmon1");
+        write_attrs(&chip, &[("name", "nct6798"), ("pwm2", "0"), ("pwm2_enable", "5"), ("pwm3", "90"), ("pwm3_enable", "1")]);
+        let curve: FanCurve = toml::from_str("chip = \"nct67*\"\npwm = \"pwm2\"\ncurve = [[40.0, 20.0], [60.0, 40.0], [80.0, 100.0]]\n\
+                                              min = 25.0\nsmoothing = 0.5\nspin_up = 60.0\n").unwrap();
+        assert_eq!(curve.check(), None);
+        assert_eq!((curve.duty(30.0), curve.duty(50.0), curve.duty(70.0), curve.duty(90.0)), (20.0, 30.0, 70.0, 100.0));
+        let manual = FanCurve { pwm: "pwm3".to_string(), spin_up: None, smoothing: 0.0, ..curve.clone() };
+        let temp = |t: f64| -> BTreeMap<String, f64> { vec![("cpu_temp".to_string(), t)].into_iter().collect() };
+        assert!(initial_fan_controller_from(dir.path(), &[FanCurve { pwm: "pwm4".to_string(), ..curve.clone() }],
+                                            &temp(45.0)).is_err());
+        // No sensor backs the metric
+        let mb = FanCurve { metric: "mb_temp".to_string(), ..curve.clone() };
+        assert!(initial_fan_controller_from(dir.path(), &[mb], &temp(45.0)).is_err());
+        assert_eq!(read(&chip.join("pwm2_enable")), "5");
+
+        let mut controller = initial_fan_controller_from(dir.path(), &[curve.clone(), manual], &temp(45.0)).unwrap();
+        assert_eq!(read(&chip.join("pwm2_enable")), "1");
+        // The stopped fan gets a kick, and then follows the curve clamped to min
+        controller.update(&temp(30.0)).unwrap();
+        assert_eq!(read(&chip.join("pwm2")), "153");
+        controller.update(&temp(30.0)).unwrap();
+        assert_eq!(read(&chip.join("pwm2")), "64");
+        // Half way to 100% because of the smoothing
+        controller.update(&temp(85.0)).unwrap();
+        assert_eq!(read(&chip.join("pwm2")), "159");
+        // Without the metric both run at max duty at once, not smoothed towards it
+        controller.update(&BTreeMap::new()).unwrap();
+        assert_eq!(read(&chip.join("pwm2")), "255");
+        assert_eq!(read(&chip.join("pwm3")), "255");
+        assert!(do_fan_statistic(&controller).starts_with("nct67* pwm2 duty %:\tavg: 61.88 | min: 25.00 | max: 100.00"));
+
+        controller.restore();
+        assert_eq!(read(&chip.join("pwm2_enable")), "5");
+        fs::write(chip.join("pwm2_enable"), "1").unwrap();
+        drop(controller);
+        assert_eq!(read(&chip.join("pwm2_enable")), "1");
+        assert_eq!(read(&chip.join("pwm3_enable")), "1");
+        assert_eq!(read(&chip.join("pwm3")), "90");
+
+        let bad = |s: &str| toml::from_str::<FanCurve>(&format!("chip = \"x\"\npwm = \"pwm1\"\n{}", s)).unwrap().check();
+        assert!(bad("curve = []\n").is_some());
+        assert!(bad("curve = [[60.0, 20.0], [40.0, 40.0]]\n").is_some());
+        assert!(bad("curve = [[60.0, 120.0]]\n").is_some());
+        assert!(bad("curve = [[60.0, 20.0]]\nmin = 50.0\nmax = 40.0\n").is_some());
+        assert!(bad("curve = [[60.0, 20.0]]\nsmoothing = 1.0\n").is_some());
+    }
+}
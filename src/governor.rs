@@ -0,0 +1,239 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::errors::*;
+use crate::util::*;
+
+const CPU_SYS_DIR: &str = "/sys/devices/system/cpu";
+const GOVERNOR_FILE: &str = "scaling_governor";
+const EPP_FILE: &str = "energy_performance_preference";
+// The governor under which the drivers pin the EPP to "performance"
+const PERFORMANCE: &str = "performance";
+
+struct CpuFreqPolicy {
+    dir: PathBuf,
+    governor: String,
+    epp: Option<String>,
+}
+
+/// The governor and EPP of every cpufreq policy as they were before the sweep. They
+/// are restored when this is dropped, so also when the sweep is interrupted.
+pub struct GovernorSettings {
+    policies: Vec<CpuFreqPolicy>,
+    restored: bool,
+}
+
+/// How the workload did in one run. Frequencies are in MHz, temperatures
+/// in °C and power in W.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RunSummary {
+    pub avg_freq: f64,
+    // None without a CPU temperature sensor
+    pub avg_temp: Option<f64>,
+    pub max_temp: Option<f64>,
+    // None without RAPL
+    pub avg_power: Option<f64>,
+    pub runtime: Duration,
+    // Whether the workload exited successfully, and wasn't interrupted
+    pub completed: bool,
+}
+
+/// One run of a sweep: the settings and how the workload did with them.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SweepResult {
+    pub governor: String,
+    pub epp: Option<String>,
+    pub summary: RunSummary,
+}
+
+// A governor and, if given, the EPP to run it with
+pub type SweepRun = (String, Option<String>);
+
+/// The (governor, EPP) pairs to run, and the pairs skipped because the driver
+/// refuses them: under the performance governor intel_pstate and amd_pstate
+/// only accept the EPP "performance", so that governor is run once.
+pub fn get_sweep_runs(governors: &[String], epps: &[String]) -> (Vec<SweepRun>, Vec<(String, String)>) {
+    let mut runs = Vec::new();
+    let mut skipped = Vec::new();
+    for g in governors {
+        if g == PERFORMANCE && !epps.is_empty() {
+            let epp = epps.iter().find(|e| e.as_str() == PERFORMANCE).cloned();
+            runs.push((g.clone(), epp));
+            skipped.extend(epps.iter().filter(|e| e.as_str() != PERFORMANCE).map(|e| (g.clone(), e.clone())));
+            continue;
+        }
+        if epps.is_empty() {
+            runs.push((g.clone(), None));
+        }
+        for e in epps {
+            runs.push((g.clone(), Some(e.clone())));
+        }
+    }
+    (runs, skipped)
+}
+
+fn save_governors_from(root: &Path) -> Result<GovernorSettings> {
+    let mut policies = Vec::new();
+    // One policy per group of CPUs sharing a clock. Its dir stays while its CPUs
+    // are offline, so CPUs which come online during the sweep are covered too.
+    for dir in list_devices(&root.join("cpufreq"), "policy") {
+        let governor = read_sysfs_string(&dir.join(GOVERNOR_FILE))?;
+        let epp = read_sysfs_string(&dir.join(EPP_FILE)).ok();
+        policies.push(CpuFreqPolicy { dir, governor, epp });
+    }
+    if policies.is_empty() {
+        bail!("No cpufreq policy is found in {}.", root.join("cpufreq").display());
+    }
+    Ok(GovernorSettings { policies, restored: false })
+}
+
+/// Save the governor settings of every cpufreq policy.
+pub fn save_governors() -> Result<GovernorSettings> {
+    save_governors_from(Path::new(CPU_SYS_DIR))
+}
+
+impl GovernorSettings {
+    /// Check that every policy supports the governors and EPP values.
+    pub fn check(&self, governors: &[String], epps: &[String]) -> Result<()> {
+        for p in &self.policies {
+            let available = read_sysfs_string(&p.dir.join("scaling_available_governors"))?;
+            if let Some(g) = governors.iter().find(|g| !available.split_whitespace().any(|a| a == g.as_str())) {
+                bail!("Governor {} is not available in {}, available: {}.", g, p.dir.display(), available);
+            }
+            if epps.is_empty() {
+                continue;
+            }
+            let available = match read_sysfs_string(&p.dir.join("energy_performance_available_preferences")) {
+                Ok(a) => a,
+                Err(_) => bail!("EPP is not supported in {}.", p.dir.display()),
+            };
+            if let Some(e) = epps.iter().find(|e| !available.split_whitespace().any(|a| a == e.as_str())) {
+                bail!("EPP {} is not available in {}, available: {}.", e, p.dir.display(), available);
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch every policy to `governor`, then to `epp` if given.
+    pub fn apply(&self, governor: &str, epp: Option<&str>) -> Result<()> {
+        for p in &self.policies {
+            write_sysfs(&p.dir.join(GOVERNOR_FILE), governor)?;
+            // The EPP can only be set after the governor, e.g. intel_pstate refuses it under "performance"
+            if let Some(e) = epp {
+                write_sysfs(&p.dir.join(EPP_FILE), e)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn restore(&mut self) {
+        if self.restored {
+            return;
+        }
+        self.restored = true;
+        for p in &self.policies {
+            let mut result = write_sysfs(&p.dir.join(GOVERNOR_FILE), &p.governor);
+            if let (Ok(()), Some(e)) = (&result, &p.epp) {
+                result = write_sysfs(&p.dir.join(EPP_FILE), e);
+            }
+            if let Err(e) = result {
+                error!("Restore {} failed: {}", p.dir.display(), e);
+            }
+        }
+        info!("CPU governors are restored.");
+    }
+}
+
+impl Drop for GovernorSettings {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+/// One comparison line per run, then one per skipped (governor, EPP) pair.
+pub fn do_sweep_statistic(results: &[SweepResult], skipped: &[(String, String)]) -> String {
+    if results.is_empty() {
+        return "Sweep:\t\t\tno run completed".to_string();
+    }
+    let mut ret = vec![format!("{:<16}{:<24}{:>10}{:>10}{:>10}{:>10}{:>12}",
+                               "Governor", "EPP", "Freq MHz", "Temp °C", "Max °C", "Power W", "Runtime s")];
+    for r in results {
+        let s = &r.summary;
+        let value = |v: Option<f64>| v.map(|v| format!("{:.2}", v)).unwrap_or_else(|| "-".to_string());
+        let runtime = format!("{:.2}{}", s.runtime.as_secs_f64(), if s.completed { "" } else { "*" });
+        ret.push(format!("{:<16}{:<24}{:>10.2}{:>10}{:>10}{:>10}{:>12}",
+                         r.governor, r.epp.as_deref().unwrap_or("-"), s.avg_freq,
+                         value(s.avg_temp), value(s.max_temp), value(s.avg_power), runtime));
+    }
+    for (governor, epp) in skipped {
+        ret.push(format!("{:<16}{:<24}skipped: the governor only takes EPP {}", governor, epp, PERFORMANCE));
+    }
+    if results.iter().any(|r| !r.summary.completed) {
+        ret.push("* the workload failed or was interrupted".to_string());
+    }
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_governor_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        for policy in 0..2 {
+            write_attrs(&dir.path().join(format!("cpufreq/policy{}", policy)), &[
+                (GOVERNOR_FILE, "powersave"),
+                ("scaling_available_governors", "performance powersave"),
+                (EPP_FILE, "balance_performance"),
+                ("energy_performance_available_preferences", "default performance balance_performance balance_power power"),
+            ]);
+        }
+        let policy1 = dir.path().join("cpufreq/policy1");
+        let settings = save_governors_from(dir.path()).unwrap();
+        let names = |n: &[&str]| -> Vec<String> { n.iter().map(|s| s.to_string()).collect() };
+        assert!(settings.check(&names(&["performance", "powersave"]), &names(&["power"])).is_ok());
+        assert!(settings.check(&names(&["schedutil"]), &[]).is_err());
+        assert!(settings.check(&names(&["powersave"]), &names(&["turbo"])).is_err());
+
+        settings.apply("powersave", Some("power")).unwrap();
+        assert_eq!((read(&policy1.join(GOVERNOR_FILE)), read(&policy1.join(EPP_FILE))), ("powersave".to_string(), "power".to_string()));
+        settings.apply("performance", None).unwrap();
+        drop(settings);
+        assert_eq!(read(&policy1.join(GOVERNOR_FILE)), "powersave");
+        assert_eq!(read(&policy1.join(EPP_FILE)), "balance_performance");
+        assert!(save_governors_from(&dir.path().join("cpu0")).is_err());
+
+        let summary = RunSummary {
+            avg_freq: 4012.333, avg_temp: Some(71.25), max_temp: Some(78.0), avg_power: Some(88.1),
+            runtime: Duration::from_millis(12340), completed: true,
+        };
+        let (runs, skipped) = get_sweep_runs(&names(&["performance", "powersave"]), &names(&["power", "performance"]));
+        assert_eq!(runs, vec![
+            ("performance".to_string(), Some("performance".to_string())),
+            ("powersave".to_string(), Some("power".to_string())),
+            ("powersave".to_string(), Some("performance".to_string())),
+        ]);
+        assert_eq!(skipped, vec![("performance".to_string(), "power".to_string())]);
+        assert_eq!(get_sweep_runs(&names(&["performance"]), &names(&["power"])).0,
+                   vec![("performance".to_string(), None)]);
+
+        let report = do_sweep_statistic(&[
+            SweepResult { governor: "performance".to_string(), epp: None, summary: summary.clone() },
+            SweepResult {
+                governor: "powersave".to_string(), epp: Some("power".to_string()),
+                summary: RunSummary { avg_temp: None, max_temp: None, avg_power: None, completed: false, ..summary },
+            },
+        ], &skipped);
+        assert_eq!(report.lines().nth(1).unwrap(),
+                   "performance     -                          4012.33     71.25     78.00     88.10       12.34");
+        assert!(report.lines().nth(2).unwrap().starts_with("powersave       power     "));
+        assert!(report.lines().nth(2).unwrap().ends_with("4012.33         -         -         -      12.34*"));
+        assert_eq!(report.lines().nth(3).unwrap(),
+                   "performance     power                   skipped: the governor only takes EPP performance");
+    }
+}
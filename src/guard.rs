@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::unistd::Pid;
+use crate::errors::*;
+use crate::statistic::*;
+use crate::util::*;
+
+// How long a terminated process may take to exit before it is killed
+const KILL_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GuardTarget {
+    Pid(i32),
+    Group(i32),
+}
+
+impl fmt::Display for GuardTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GuardTarget::Pid(pid) => write!(f, "pid {}", pid),
+            GuardTarget::Group(pgid) => write!(f, "process group {}", pgid),
+        }
+    }
+}
+
+impl GuardTarget {
+    // `None` only checks that the target still exists
+    fn signal<T: Into<Option<Signal>>>(self, signal: T) -> nix::Result<()> {
+        match self {
+            GuardTarget::Pid(pid) => kill(Pid::from_raw(pid), signal),
+            GuardTarget::Group(pgid) => killpg(Pid::from_raw(pgid), signal),
+        }
+    }
+}
+
+/// Pause the target once `metric` reaches `critical`, resume it below
+/// `resume`, and terminate it if it stays paused for `grace`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct GuardConfig {
+    pub target: GuardTarget,
+    pub metric: String,
+    pub critical: f64,
+    pub resume: f64,
+    pub grace: Option<Duration>,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GuardAction {
+    Paused,
+    Resumed,
+    Terminated,
+    Killed,
+}
+
+impl fmt::Display for GuardAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            GuardAction::Paused => "paused (SIGSTOP)",
+            GuardAction::Resumed => "resumed (SIGCONT)",
+            GuardAction::Terminated => "terminated (SIGTERM)",
+            GuardAction::Killed => "killed (SIGKILL)",
+        })
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Intervention {
+    pub time: Duration,
+    pub action: GuardAction,
+    // The metric at that time, None when monitoring stopped
+    pub value: Option<f64>,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum GuardState {
+    Running,
+    Paused(Duration),
+    Terminating(Duration),
+    // The target is gone, or was killed
+    Done,
+}
+
+pub struct Guard {
+    pub config: GuardConfig,
+    state: GuardState,
+    pub interventions: Vec<Intervention>,
+}
+
+impl Guard {
+    /// Guard by `config`, whose metric has to be one of `metrics`, the sensor
+    /// metrics read at start.
+    pub fn new(config: GuardConfig, metrics: &BTreeMap<String, f64>) -> Result<Guard> {
+        if !metrics.contains_key(&config.metric) {
+            bail!("No sensor backs the guard metric {}.", config.metric);
+        }
+        if config.resume >= config.critical {
+            bail!("The guard resume threshold {} must be below the critical one {}.", config.resume, config.critical);
+        }
+        config.target.signal(None).chain_err(|| format!("Cannot signal {}.", config.target))?;
+        Ok(Guard { config, state: GuardState::Running, interventions: Vec::new() })
+    }
+
+    fn act(&mut self, time: Duration, action: GuardAction, value: Option<f64>) {
+        let signals: &[Signal] = match action {
+            GuardAction::Paused => &[Signal::SIGSTOP],
+            GuardAction::Resumed => &[Signal::SIGCONT],
+            // A stopped process only handles SIGTERM once it continues
+            GuardAction::Terminated => &[Signal::SIGTERM, Signal::SIGCONT],
+            GuardAction::Killed => &[Signal::SIGKILL],
+        };
+        for s in signals {
+            if let Err(e) = self.config.target.signal(*s) {
+                warn!("Guard: sending {:?} to {} failed: {}", s, self.config.target, e);
+                self.state = GuardState::Done;
+                return;
+            }
+        }
+        let value_str = value.map(|v| format!(" at {} = {:.2}", self.config.metric, v)).unwrap_or_default();
+        match action {
+            GuardAction::Resumed => info!("Guard: {} {}{}", self.config.target, action, value_str),
+            _ => error!("Guard: {} {}{}", self.config.target, action, value_str),
+        }
+        self.interventions.push(Intervention { time, action, value });
+    }
+
+    /// Feed the guarded metric of one sample, None if it is unavailable.
+    pub fn update(&mut self, time: Duration, value: Option<f64>) {
+        if self.state != GuardState::Done && self.config.target.signal(None).is_err() {
+            info!("Guard: {} has exited.", self.config.target);
+            self.state = GuardState::Done;
+        }
+        if value.is_none() && self.state != GuardState::Done {
+            error!("Guard: {} is unavailable in this sample, {} is left as it is.", self.config.metric, self.config.target);
+        }
+        match self.state {
+            GuardState::Running => {
+                if let Some(v) = value.filter(|v| *v >= self.config.critical) {
+                    self.act(time, GuardAction::Paused, Some(v));
+                    if self.state == GuardState::Running {
+                        self.state = GuardState::Paused(time);
+                    }
+                }
+            },
+            GuardState::Paused(since) => {
+                if let Some(v) = value.filter(|v| *v < self.config.resume) {
+                    self.act(time, GuardAction::Resumed, Some(v));
+                    if self.state != GuardState::Done {
+                        self.state = GuardState::Running;
+                    }
+                } else if self.config.grace.is_some_and(|g| time - since >= g) {
+                    self.act(time, GuardAction::Terminated, value);
+                    if self.state != GuardState::Done {
+                        self.state = GuardState::Terminating(time);
+                    }
+                }
+            },
+            GuardState::Terminating(since) => {
+                if time - since >= KILL_DELAY {
+                    self.act(time, GuardAction::Killed, value);
+                    self.state = GuardState::Done;
+                }
+            },
+            GuardState::Done => (),
+        }
+    }
+
+    /// Don't leave the target stopped when monitoring stops.
+    pub fn finish(&mut self, time: Duration) {
+        if let GuardState::Paused(_) = self.state {
+            self.act(time, GuardAction::Resumed, None);
+        }
+        self.state = GuardState::Done;
+    }
+}
+
+pub fn do_guard_statistic(config: &GuardConfig, interventions: &[Intervention]) -> String {
+    let title = format!("Guard {}:", config.target);
+    if interventions.is_empty() {
+        return format!("{}no intervention, {} stayed below {:.2}", title_tabs(&title), config.metric, config.critical);
+    }
+    let mut ret = vec![format!("{}{} intervention(s)", title_tabs(&title), interventions.len())];
+    for (i, e) in interventions.iter().enumerate() {
+        let value = match e.value {
+            Some(v) => format!("{}: {:.2}", config.metric, v),
+            None => "monitoring stopped".to_string(),
+        };
+        ret.push(format!("Guard #{}:\t\t{} | {} | {}", i + 1, format_elapsed(e.time), e.action, value));
+    }
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process::Command;
+    use std::thread;
+
+    // Signals are delivered asynchronously, so wait a moment for the state to change
+    fn is_stopped(pid: u32, expected: bool) -> bool {
+        for _ in 0..100 {
+            let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+            if stat[stat.rfind(')').unwrap() + 2..].starts_with('T') == expected {
+                return expected;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        !expected
+    }
+
+    #[test]
+    fn test_guard() {
+        let mut child = Command::new("sleep").arg("60").spawn().unwrap();
+        let config = GuardConfig {
+            target: GuardTarget::Pid(child.id() as i32), metric: "cpu_temp".to_string(),
+            critical: 95.0, resume: 80.0, grace: Some(Duration::from_secs(30)),
+        };
+        let metrics: BTreeMap<String, f64> = [("cpu_temp".to_string(), 45.0)].iter().cloned().collect();
+        assert!(Guard::new(GuardConfig { resume: 95.0, ..config.clone() }, &metrics).is_err());
+        assert!(Guard::new(GuardConfig { metric: "mb_temp".to_string(), ..config.clone() }, &metrics).is_err());
+        let mut guard = Guard::new(config.clone(), &metrics).unwrap();
+        let secs = Duration::from_secs;
+
+        guard.update(secs(3), Some(90.0));
+        guard.update(secs(6), Some(95.5));
+        assert!(is_stopped(child.id(), true));
+        // Still above the resume threshold
+        guard.update(secs(9), Some(85.0));
+        guard.update(secs(12), None);
+        guard.update(secs(15), Some(79.0));
+        assert!(!is_stopped(child.id(), false));
+        guard.update(secs(18), Some(97.0));
+        guard.update(secs(48), Some(90.0));
+        let status = child.wait().unwrap();
+        assert_eq!(status.to_string(), "signal: 15 (SIGTERM)");
+        guard.update(secs(51), Some(90.0));
+
+        let actions: Vec<(u64, GuardAction)> = guard.interventions.iter().map(|e| (e.time.as_secs(), e.action)).collect();
+        assert_eq!(actions, vec![(6, GuardAction::Paused), (15, GuardAction::Resumed),
+                                 (18, GuardAction::Paused), (48, GuardAction::Terminated)]);
+        let report = do_guard_statistic(&config, &guard.interventions);
+        let title = format!("Guard pid {}:", child.id());
+        assert!(report.starts_with(&format!("{}4 intervention(s)\n\
+                                             Guard #1:\t\t+00:00:06 | paused (SIGSTOP) | cpu_temp: 95.50", title_tabs(&title))));
+
+        let mut child = Command::new("sleep").arg("60").spawn().unwrap();
+        let mut guard = Guard::new(GuardConfig { target: GuardTarget::Pid(child.id() as i32), ..config }, &metrics).unwrap();
+        guard.update(secs(3), Some(100.0));
+        guard.finish(secs(6));
+        assert_eq!(guard.interventions[1], Intervention { time: secs(6), action: GuardAction::Resumed, value: None });
+        assert!(!is_stopped(child.id(), false));
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
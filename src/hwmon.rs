@@ -0,0 +1,418 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::statistic::*;
+use crate::util::*;
+
+pub const HWMON_SYS_DIR: &str = "/sys/class/hwmon";
+// Temperatures closer than this to a limit count as "near" it
+const NEAR_LIMIT: f64 = 5.0;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SensorKind {
+    Voltage,
+    Current,
+    Power,
+    Temperature,
+    Fan,
+}
+
+const SENSOR_KINDS: [SensorKind; 5] =
+    [SensorKind::Temperature, SensorKind::Fan, SensorKind::Voltage, SensorKind::Current, SensorKind::Power];
+
+impl SensorKind {
+    fn prefix(self) -> &'static str {
+        match self {
+            SensorKind::Voltage => "in",
+            SensorKind::Current => "curr",
+            SensorKind::Power => "power",
+            SensorKind::Temperature => "temp",
+            SensorKind::Fan => "fan",
+        }
+    }
+
+    /// The kind of an input like "temp1", None for other inputs.
+    pub fn from_attr(attr: &str) -> Option<SensorKind> {
+        SENSOR_KINDS.iter().cloned().find(|k| {
+            attr.strip_prefix(k.prefix()).is_some_and(|n| !n.is_empty() && n.bytes().all(|c| c.is_ascii_digit()))
+        })
+    }
+
+    pub fn unit(self) -> &'static str {
+        match self {
+            SensorKind::Voltage => "V",
+            SensorKind::Current => "A",
+            SensorKind::Power => "W",
+            SensorKind::Temperature => "°C",
+            SensorKind::Fan => "RPM",
+        }
+    }
+
+    // sysfs values are in mV, mA, µW, m°C and RPM
+    fn scale(self) -> f64 {
+        match self {
+            SensorKind::Voltage | SensorKind::Current | SensorKind::Temperature => 1000.0,
+            SensorKind::Power => 1_000_000.0,
+            SensorKind::Fan => 1.0,
+        }
+    }
+}
+
+struct HwmonSensor {
+    name: String,
+    device: String,
+    chip: String,
+    label: String,
+    attr: String,
+    kind: SensorKind,
+    // e.g. ".../hwmon2/temp1", the attributes are this plus a suffix
+    base: PathBuf,
+    // Suffix of the value, "_input" or for some power sensors "_average"
+    input: &'static str,
+    min: Option<f64>,
+    max: Option<f64>,
+    crit: Option<f64>,
+}
+
+pub struct HwmonStat {
+    sensors: Vec<HwmonSensor>,
+}
+
+/// A reading in V, A, W, °C or RPM, with the hardware limits of the sensor.
+#[derive(PartialEq, Debug, Clone)]
+pub struct HwmonEntry {
+    // "<chip> <label>", chip is the hwmon name and attr the input, e.g. "temp1".
+    // Chips of the same name are told apart by device, which is then in the
+    // name too, e.g. "nvme@nvme1 Composite".
+    pub name: String,
+    pub device: String,
+    pub chip: String,
+    pub label: String,
+    pub attr: String,
+    pub kind: SensorKind,
+    pub value: f64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub crit: Option<f64>,
+    // Whether any alarm bit of the sensor is raised
+    pub alarm: bool,
+}
+
+// Older drivers put the attributes in the device directory
+fn attr_dir(dir: &Path) -> PathBuf {
+    if dir.join("name").exists() { dir.to_path_buf() } else { dir.join("device") }
+}
+
+fn attr(base: &Path, suffix: &str) -> PathBuf {
+    let mut name = base.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    base.with_file_name(name)
+}
+
+// The device a hwmon chip belongs to, e.g. "nvme0" or "coretemp.1", which
+// unlike hwmonN stays the same across boots
+fn device_id(dir: &Path) -> String {
+    let hwmon = if dir.ends_with("device") { dir.parent().unwrap_or(dir) } else { dir };
+    fs::read_link(hwmon.join("device")).ok()
+        .and_then(|l| l.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| hwmon.file_name().unwrap_or_default().to_string_lossy().into_owned())
+}
+
+/// The "<chip> <label>" names of (device, chip, label) sensors, with the
+/// device in those which several devices share.
+pub fn get_sensor_names(sensors: &[(&str, &str, &str)]) -> Vec<String> {
+    let plain: Vec<String> = sensors.iter().map(|(_, c, l)| format!("{} {}", c, l)).collect();
+    sensors.iter().zip(&plain)
+        .map(|((device, chip, label), name)| {
+            if sensors.iter().zip(&plain).any(|((d, _, _), n)| n == name && d != device) {
+                format!("{}@{} {}", chip, device, label)
+            } else {
+                name.clone()
+            }
+        })
+        .collect()
+}
+
+fn find_sensors(dir: &Path, chip: &str, kind: SensorKind, sensors: &mut Vec<HwmonSensor>) {
+    let prefix = kind.prefix();
+    let mut indexes: Vec<usize> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name().to_string_lossy().into_owned();
+                name.strip_prefix(prefix)
+                    .and_then(|n| {
+                        n.strip_suffix("_input")
+                            .or_else(|| n.strip_suffix("_average").filter(|_| kind == SensorKind::Power))
+                    })
+                    .and_then(|n| n.parse::<usize>().ok())
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    indexes.sort_unstable();
+    indexes.dedup();
+    for n in indexes {
+        let name = format!("{}{}", prefix, n);
+        let base = dir.join(&name);
+        // Some power sensors only have an average, e.g. the PPT of amdgpu
+        let input = if attr(&base, "_input").exists() { "_input" } else { "_average" };
+        let label = fs::read_to_string(attr(&base, "_label"))
+            .map(|l| l.trim().to_string())
+            .unwrap_or_else(|_| name.clone());
+        // A limit of 0 means it is not set
+        let limit = |suffix: &str| read_sysfs_i64(&attr(&base, suffix)).ok()
+            .filter(|v| *v != 0)
+            .map(|v| v as f64 / kind.scale());
+        sensors.push(HwmonSensor {
+            name: format!("{} {}", chip, label),
+            device: device_id(dir),
+            chip: chip.to_string(),
+            label,
+            attr: name,
+            kind,
+            min: limit("_min"),
+            max: limit("_max"),
+            crit: limit("_crit"),
+            base,
+            input,
+        });
+    }
+}
+
+/// The name and attribute directory of every hwmon chip under `root`.
+pub fn find_chips(root: &Path) -> Vec<(String, PathBuf)> {
+    let mut chips: Vec<PathBuf> = match fs::read_dir(root) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => Vec::new(),
+    };
+    chips.sort();
+    chips.iter()
+        .map(|c| attr_dir(c))
+        .filter_map(|dir| fs::read_to_string(dir.join("name")).ok().map(|n| (n.trim().to_string(), dir)))
+        .collect()
+}
+
+fn initial_hwmon_stat_from(root: &Path) -> HwmonStat {
+    let mut sensors = Vec::new();
+    for (name, dir) in find_chips(root) {
+        for kind in SENSOR_KINDS.iter() {
+            find_sensors(&dir, &name, *kind, &mut sensors);
+        }
+    }
+    let keys: Vec<(&str, &str, &str)> = sensors.iter().map(|s| (&s.device[..], &s.chip[..], &s.label[..])).collect();
+    let names = get_sensor_names(&keys);
+    for (s, name) in sensors.iter_mut().zip(names) {
+        s.name = name;
+    }
+    HwmonStat { sensors }
+}
+
+/// Find the sensors of every hwmon chip.
+pub fn initial_hwmon_stat() -> HwmonStat {
+    initial_hwmon_stat_from(Path::new(HWMON_SYS_DIR))
+}
+
+pub fn collect_hwmon_info(stat: &HwmonStat) -> Vec<HwmonEntry> {
+    let mut result = Vec::new();
+    for s in &stat.sensors {
+        // Some chips return errors for inputs which are not connected
+        let value = match read_sysfs_i64(&attr(&s.base, s.input)) {
+            Ok(v) => v as f64 / s.kind.scale(),
+            Err(_) => continue,
+        };
+        let alarm = ["_alarm", "_min_alarm", "_max_alarm", "_crit_alarm"].iter()
+            .any(|suffix| read_sysfs_u64(&attr(&s.base, suffix)).map(|a| a != 0).unwrap_or(false));
+        result.push(HwmonEntry {
+            name: s.name.clone(),
+            device: s.device.clone(),
+            chip: s.chip.clone(),
+            label: s.label.clone(),
+            attr: s.attr.clone(),
+            kind: s.kind,
+            value,
+            min: s.min,
+            max: s.max,
+            crit: s.crit,
+            alarm,
+        });
+    }
+    result
+}
+
+// Lowest headroom to the limit and the seconds spent within NEAR_LIMIT of
+// it, from (seconds, value, limit) samples
+fn headroom(samples: &[(f64, f64, Option<f64>)]) -> Option<(f64, f64)> {
+    let mut min = None;
+    let mut near = 0.0;
+    for (secs, value, limit) in samples {
+        if let Some(l) = limit {
+            let h = l - value;
+            min = Some(min.map_or(h, |m: f64| m.min(h)));
+            if h < NEAR_LIMIT {
+                near += secs;
+            }
+        }
+    }
+    min.map(|m| (m, near))
+}
+
+/// The headroom line of a temperature from (seconds, value, max, crit)
+/// samples, None if it has no limit.
+pub fn format_temp_headroom(name: &str, samples: &[(f64, f64, Option<f64>, Option<f64>)]) -> Option<String> {
+    let mut parts = Vec::new();
+    let max: Vec<(f64, f64, Option<f64>)> = samples.iter().map(|(secs, v, max, _)| (*secs, *v, *max)).collect();
+    let crit: Vec<(f64, f64, Option<f64>)> = samples.iter().map(|(secs, v, _, crit)| (*secs, *v, *crit)).collect();
+    for (limit, s) in &[("max", max), ("crit", crit)] {
+        if let Some((h, near)) = headroom(s) {
+            parts.push(format!("to {}: {:.1}°C, {:.0}s within {}°C", limit, h, near, NEAR_LIMIT));
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    Some(format!("{}{}", title_tabs(&format!("{} headroom:", name)), parts.join(" | ")))
+}
+
+/// The headroom line of a fan from (value, min) samples, None if it has no minimum.
+pub fn format_fan_headroom(name: &str, samples: &[(f64, Option<f64>)]) -> Option<String> {
+    let above: Vec<f64> = samples.iter().filter_map(|(v, min)| min.map(|m| v - m)).collect();
+    if above.is_empty() {
+        return None;
+    }
+    Some(format!("{}above min: {:.0} RPM", title_tabs(&format!("{} headroom:", name)),
+                 above.iter().cloned().fold(f64::MAX, f64::min)))
+}
+
+/// The alarm line from the times of the samples with a raised alarm, None if there is none.
+pub fn format_alarms(name: &str, alarms: &[Duration]) -> Option<String> {
+    alarms.first().map(|first| format!("{}raised in {} samples, first at {}",
+                                       title_tabs(&format!("{} ALARM:", name)), alarms.len(), format_elapsed(*first)))
+}
+
+/// Every sample comes with its time and the interval it stands for, to tell
+/// how long sensors were near their limits.
+pub fn do_hwmon_statistic(samples: &[(Duration, Duration, &[HwmonEntry])]) -> String {
+    // A sensor is its device and input, the name only labels it
+    let mut sensors: Vec<(&str, &str, &str, SensorKind)> = Vec::new();
+    for (_, _, s) in samples {
+        for h in s.iter() {
+            if !sensors.iter().any(|(d, a, _, _)| *d == h.device && *a == h.attr) {
+                sensors.push((&h.device, &h.attr, &h.name, h.kind));
+            }
+        }
+    }
+    if sensors.is_empty() {
+        return "Hwmon:\t\t\tno hwmon sensor".to_string();
+    }
+    let timed: Vec<(Duration, f64, &[HwmonEntry])> = samples.iter()
+        .map(|(time, interval, s)| (*time, interval.as_secs_f64(), *s))
+        .collect();
+    let mut ret = Vec::new();
+    for (device, attr, name, kind) in sensors {
+        let entries: Vec<(Duration, f64, &HwmonEntry)> = timed.iter()
+            .flat_map(|(t, secs, s)| s.iter().filter(|h| h.device == device && h.attr == attr).map(move |h| (*t, *secs, h)))
+            .collect();
+        let values: Vec<f64> = entries.iter().map(|(_, _, h)| h.value).collect();
+        let title = format!("{} {}:", name, kind.unit());
+        match kind {
+            SensorKind::Temperature => {
+                ret.push(format_stat(&title, &values, &[40.0, 60.0, 80.0, 90.0],
+                                     &["<40°C", "40°C-60°C", "60°C-80°C", "80°C-90°C", ">=90°C"]));
+                let limits: Vec<(f64, f64, Option<f64>, Option<f64>)> = entries.iter()
+                    .map(|(_, secs, h)| (*secs, h.value, h.max, h.crit))
+                    .collect();
+                ret.extend(format_temp_headroom(name, &limits));
+            },
+            SensorKind::Fan => {
+                ret.push(format_stat(&title, &values, &[500.0, 1000.0, 2000.0],
+                                     &["<500", "500-1000", "1000-2000", ">=2000"]));
+                let mins: Vec<(f64, Option<f64>)> = entries.iter().map(|(_, _, h)| (h.value, h.min)).collect();
+                ret.extend(format_fan_headroom(name, &mins));
+            },
+            _ => {
+                let min = values.iter().cloned().fold(f64::MAX, f64::min);
+                let max = values.iter().cloned().fold(f64::MIN, f64::max);
+                let avg = values.iter().sum::<f64>() / values.len() as f64;
+                // Ripple shows how much a rail sags under load
+                ret.push(format!("{}avg: {:.3} | min: {:.3} | max: {:.3} | ripple: {:.3}",
+                                 title_tabs(&title), avg, min, max, max - min));
+            },
+        }
+        let alarms: Vec<Duration> = entries.iter().filter(|(_, _, h)| h.alarm).map(|(t, _, _)| *t).collect();
+        ret.extend(format_alarms(name, &alarms));
+    }
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_hwmon_info() {
+        let secs = Duration::from_secs;
+        let dir = tempfile::tempdir().unwrap();
+        let chip = dir.path().join("hwmon2");
+        write_attrs(&chip, &[("name", "nct6798"), ("in0_input", "1248"), ("in0_label", "Vcore"),
+                             ("in1_input", "12096"), ("curr1_input", "15500"), ("power1_input", "65250000"),
+                             ("power2_average", "35000000"), ("power2_label", "PPT")]);
+        write_attrs(&dir.path().join("hwmon0/device"), &[("name", "it87"), ("in3_input", "3312")]);
+
+        let stat = initial_hwmon_stat_from(dir.path());
+        let first = collect_hwmon_info(&stat);
+        let values: Vec<(&str, f64)> = first.iter().map(|h| (h.name.as_str(), h.value)).collect();
+        assert_eq!(values, vec![("it87 in3", 3.312), ("nct6798 Vcore", 1.248), ("nct6798 in1", 12.096),
+                                ("nct6798 curr1", 15.5), ("nct6798 power1", 65.25), ("nct6798 PPT", 35.0)]);
+        assert_eq!(first[5].attr, "power2");
+        assert_eq!(first[3].kind, SensorKind::Current);
+
+        fs::write(chip.join("in1_input"), "11712\n").unwrap();
+        let second = collect_hwmon_info(&stat);
+        let report = do_hwmon_statistic(&[(secs(3), secs(3), &first), (secs(6), secs(3), &second)]);
+        assert!(report.contains("nct6798 in1 V:\t\tavg: 11.904 | min: 11.712 | max: 12.096 | ripple: 0.384"));
+
+        // Two drives of the same model keep their own lines
+        for (hwmon, drive, temp) in &[("hwmon3", "nvme0", "40850"), ("hwmon4", "nvme1", "60850")] {
+            fs::create_dir_all(dir.path().join(drive)).unwrap();
+            write_attrs(&dir.path().join(hwmon), &[("name", "nvme"), ("temp1_input", temp), ("temp1_label", "Composite")]);
+            std::os::unix::fs::symlink(dir.path().join(drive), dir.path().join(hwmon).join("device")).unwrap();
+        }
+        let stat = initial_hwmon_stat_from(dir.path());
+        let entries = collect_hwmon_info(&stat);
+        let names: Vec<&str> = entries.iter().filter(|h| h.chip == "nvme").map(|h| h.name.as_str()).collect();
+        assert_eq!(names, vec!["nvme@nvme0 Composite", "nvme@nvme1 Composite"]);
+        let report = do_hwmon_statistic(&[(secs(3), secs(3), &entries)]);
+        assert!(report.contains("nvme@nvme1 Composite °C:\tavg: 60.85"));
+        assert_eq!(get_sensor_names(&[("hwmon0", "it87", "in3")]), vec!["it87 in3"]);
+    }
+
+    #[test]
+    fn test_hwmon_limits() {
+        let secs = Duration::from_secs;
+        let dir = tempfile::tempdir().unwrap();
+        let chip = dir.path().join("hwmon1");
+        write_attrs(&chip, &[("name", "k10temp"), ("temp1_input", "70000"), ("temp1_label", "Tctl"),
+                             ("temp1_max", "80000"), ("temp1_crit", "95000"), ("temp1_crit_alarm", "0"),
+                             ("fan1_input", "900"), ("fan1_min", "600"), ("fan1_alarm", "0"), ("fan2_input", "0"),
+                             ("fan2_min", "0")]);
+        let stat = initial_hwmon_stat_from(dir.path());
+        let first = collect_hwmon_info(&stat);
+        assert_eq!(first[0], HwmonEntry {
+            name: "k10temp Tctl".to_string(), device: "hwmon1".to_string(), chip: "k10temp".to_string(), label: "Tctl".to_string(),
+            attr: "temp1".to_string(), kind: SensorKind::Temperature, value: 70.0,
+            min: None, max: Some(80.0), crit: Some(95.0), alarm: false,
+        });
+        assert_eq!(first[2].min, None);
+
+        write_attrs(&chip, &[("temp1_input", "77500"), ("fan1_input", "550"), ("fan1_alarm", "1")]);
+        let second = collect_hwmon_info(&stat);
+        assert!(second[1].alarm);
+        let report = do_hwmon_statistic(&[(secs(3), secs(3), &first), (secs(6), secs(3), &second)]);
+        assert!(report.contains("k10temp Tctl headroom:\tto max: 2.5°C, 3s within 5°C | to crit: 17.5°C, 0s within 5°C"));
+        assert!(report.contains("k10temp fan1 headroom:\tabove min: -50 RPM"));
+        assert!(report.contains("k10temp fan1 ALARM:\traised in 1 samples, first at +00:00:06"));
+        // The first sample after a reset only stands for its own interval
+        let report = do_hwmon_statistic(&[(secs(60), secs(3), &second)]);
+        assert!(report.contains("to max: 2.5°C, 3s within 5°C"));
+    }
+}
@@ -10,31 +10,276 @@ mod errors;
 mod cpu;
 mod hdd;
 mod sensors;
+mod throttle;
+mod power;
+mod cpuidle;
+mod topology;
+mod statistic;
+mod memory;
+mod diskstat;
+mod netdev;
+mod pressure;
+mod sched;
+mod process;
+mod cgroup;
+mod thermal;
+mod hwmon;
+mod profile;
+mod alert;
+mod config;
+mod action;
+mod guard;
+mod fan;
+mod governor;
+mod util;
+mod parser;
 
 use std::thread;
 use std::env;
-use std::time::Duration;
+use std::fs;
+use std::io;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
 use log::LevelFilter;
-use clap::App;
+use clap::{App, AppSettings, ArgMatches, SubCommand};
 use nix::sys::signal::*;
+use nix::unistd::{setpgid, Pid};
 use errors::*;
 use cpu::*;
 use hdd::*;
 use sensors::*;
+use throttle::*;
+use power::*;
+use cpuidle::*;
+use topology::*;
+use statistic::*;
+use memory::*;
+use diskstat::*;
+use netdev::*;
+use pressure::*;
+use sched::*;
+use process::*;
+use cgroup::*;
+use thermal::*;
+use hwmon::*;
+use profile::*;
+use alert::*;
+use config::*;
+use action::*;
+use guard::*;
+use fan::*;
+use governor::*;
+use util::format_elapsed;
 
 const DEFAULT_INTERVAL: u64 = 3;
-static mut QUIT: bool = false;
+const DEFAULT_TEMP_LIMIT: f64 = 95.0;
+const DEFAULT_GUARD_TEMP: f64 = 90.0;
+static QUIT: AtomicBool = AtomicBool::new(false);
+// Set by SIGUSR1, SIGUSR2 and SIGHUP, and handled between two samples
+static REPORT: AtomicBool = AtomicBool::new(false);
+static RESET: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
 
 #[derive(PartialEq, Debug)]
 struct PemonEntry {
+    time: Duration,
+    // Since the previous sample, or the reset
+    interval: Duration,
     cpu_info: Vec<CpuInfoEntry>,
     sensor: Sensor,
-    hdd_temp: usize,
+    hdd_temp: f64,
+    throttle_counts: BTreeMap<usize, u64>,
+    hotplug: Vec<CpuHotplugEvent>,
+    power: Option<PowerEntry>,
+    memory: MemoryEntry,
+    disks: Vec<DiskEntry>,
+    net: Vec<NetEntry>,
+    pressure: Vec<PressureEntry>,
+    sched: SchedEntry,
+    process: Option<ProcessEntry>,
+    cgroup: Option<CgroupEntry>,
+    thermal: ThermalEntry,
+    hwmon: Vec<HwmonEntry>,
+}
+
+// States which collectors need to remember between two samples
+struct PemonStat {
+    start: Instant,
+    // Time of the previous sample, or the reset
+    last: Duration,
+    cpu: BTreeMap<usize, CpuStat>,
+    rapl: RaplStat,
+    idle: CpuIdleStat,
+    vm: VmStat,
+    disk: DiskIoStat,
+    net: NetDevIoStat,
+    pressure: PressureStat,
+    sched: SchedStat,
+    process: Option<ProcessStat>,
+    cgroup: Option<CgroupStat>,
+    thermal: ThermalStat,
+    sensors: SensorBackend,
+    profile: BoardProfile,
+}
+
+// What reacts to the samples
+struct Watchers {
+    throttle: ThrottleDetector,
+    alerts: AlertMonitor,
+    actions: Option<ActionRunner>,
+    guard: Option<Guard>,
+    fans: Option<FanController>,
+}
+
+impl Watchers {
+    fn update(&mut self, entry: &PemonEntry) -> Result<()> {
+        self.throttle.update(entry.time, &entry.cpu_info, &entry.throttle_counts, entry.sensor.cpu_temp.map(|r| r.value));
+        let metrics = get_metric_values(entry);
+        for change in self.alerts.update(entry.time, &metrics) {
+            if let Some(a) = self.actions.as_mut() {
+                a.run(&change);
+            }
+        }
+        if let Some(g) = self.guard.as_mut() {
+            g.update(entry.time, metrics.get(&g.config.metric).cloned());
+        }
+        if let Some(f) = self.fans.as_mut() {
+            f.update(&metrics).chain_err(|| "Fan control failed.")?;
+        }
+        Ok(())
+    }
+
+    /// Forget what was watched so far, and keep watching.
+    fn reset(&mut self, time: Duration) {
+        self.throttle.reset(time);
+        self.alerts.reset();
+        if let Some(g) = self.guard.as_mut() {
+            g.interventions.clear();
+        }
+        if let Some(f) = self.fans.as_mut() {
+            f.reset();
+        }
+    }
+
+    /// Watch with the thresholds and actions of a reloaded config.
+    fn reload(&mut self, config: &Config, temp_limit: f64, time: Duration) {
+        self.throttle.set_temp_limit(temp_limit);
+        self.alerts.reload(config.alerts.clone(), time);
+        let actions = self.actions.take();
+        self.actions = config.action.clone().map(|c| match actions {
+            Some(mut a) => {
+                a.reload(c);
+                a
+            },
+            None => ActionRunner::new(c),
+        });
+    }
+
+    fn finish(&mut self, time: Duration) {
+        self.throttle.finish();
+        if let Some(a) = self.actions.as_mut() {
+            a.finish();
+        }
+        if let Some(g) = self.guard.as_mut() {
+            g.finish(time);
+        }
+        if let Some(f) = self.fans.as_mut() {
+            f.restore();
+        }
+    }
+}
+
+// The command a sweep runs
+struct Workload {
+    child: Child,
+    start: Instant,
+    // When it exited, and whether it succeeded
+    end: Option<(Instant, bool)>,
+}
+
+impl Workload {
+    fn spawn(cmd: &[String]) -> Result<Workload> {
+        let child;
+        unsafe {
+            child = Command::new(&cmd[0])
+                    .args(&cmd[1..])
+                    // Its own process group, so that killing it also kills what it started
+                    // Panicking in the forked child isn't safe, so a failure is returned to spawn()
+                    .pre_exec(|| {
+                        setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(|_| io::Error::last_os_error())
+                    })
+                    .spawn()
+                    .chain_err(|| format!("Run {} failed.", cmd[0]))?;
+        }
+        Ok(Workload { child, start: Instant::now(), end: None })
+    }
+
+    fn exited(&mut self) -> bool {
+        if self.end.is_none() {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                self.end = Some((Instant::now(), status.success()));
+            }
+        }
+        self.end.is_some()
+    }
+
+    /// Kill the workload if it still runs, and return its runtime and
+    /// whether it completed successfully.
+    fn finish(&mut self) -> (Duration, bool) {
+        if !self.exited() {
+            info!("Kill the workload.");
+            let _ = killpg(Pid::from_raw(self.child.id() as i32), SIGKILL);
+            let _ = self.child.wait();
+        }
+        match self.end {
+            Some((end, success)) => (end - self.start, success),
+            None => (self.start.elapsed(), false),
+        }
+    }
+}
+
+/// Wait until `end`, or until told to quit or the workload exits. Returns
+/// false early if a SIGUSR1, SIGUSR2 or SIGHUP has to be handled first.
+fn wait_until(end: Instant, mut workload: Option<&mut Workload>) -> bool {
+    while Instant::now() < end {
+        if QUIT.load(Ordering::SeqCst) || workload.as_mut().is_some_and(|w| w.exited()) {
+            return true;
+        }
+        if REPORT.load(Ordering::SeqCst) || RESET.load(Ordering::SeqCst) || RELOAD.load(Ordering::SeqCst) {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(50).min(end.saturating_duration_since(Instant::now())));
+    }
+    true
+}
+
+// Returns the pending (report, reset, reload) requests, and clears them
+fn take_requests() -> (bool, bool, bool) {
+    (REPORT.swap(false, Ordering::SeqCst), RESET.swap(false, Ordering::SeqCst), RELOAD.swap(false, Ordering::SeqCst))
 }
 
 extern "C" fn terminate(_: nix::libc::c_int)
 {
-    unsafe { QUIT = true; }
+    QUIT.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn interim_report(_: nix::libc::c_int)
+{
+    REPORT.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn reset(_: nix::libc::c_int)
+{
+    RESET.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn reload(_: nix::libc::c_int)
+{
+    RELOAD.store(true, Ordering::SeqCst);
 }
 
 fn register_signals() -> Result<()> {
@@ -45,388 +290,396 @@ fn register_signals() -> Result<()> {
     );
     unsafe { sigaction(SIGINT, &act) }.chain_err(|| "Register SIGINT failed.")?;
     unsafe { sigaction(SIGTERM, &act) }.chain_err(|| "Register SIGTERM failed.")?;
+    let handlers: [(Signal, extern "C" fn(nix::libc::c_int)); 3] =
+        [(SIGUSR1, interim_report), (SIGUSR2, reset), (SIGHUP, reload)];
+    for (signal, handler) in handlers.iter() {
+        let act = SigAction::new(SigHandler::Handler(*handler), SaFlags::empty(), SigSet::empty());
+        unsafe { sigaction(*signal, &act) }.chain_err(|| format!("Register {:?} failed.", signal))?;
+    }
     Ok(())
 }
 
-fn collect(cpu_stats: &mut Vec<CpuStat>) -> Result<PemonEntry> {
-    let cpu_info = collect_cpu_info(cpu_stats)?;
-    let sensor = get_sensor_info()?;
+/// Print the report, or write it to `file` if given.
+fn output_report(report: &str, file: Option<&str>) {
+    if let Some(f) = file {
+        match fs::write(f, format!("{}\n", report)) {
+            Ok(()) => {
+                info!("The report is written to {}.", f);
+                return;
+            },
+            Err(e) => error!("Write the report to {} failed: {}", f, e),
+        }
+    }
+    println!();
+    println!("{}", report);
+}
+
+fn collect(stat: &mut PemonStat) -> Result<PemonEntry> {
+    let time = stat.start.elapsed();
+    let interval = time.saturating_sub(stat.last);
+    stat.last = time;
+    let old_cpus: Vec<usize> = stat.cpu.keys().cloned().collect();
+    let mut cpu_info = collect_cpu_info(&mut stat.cpu)?;
+    let new_cpus: Vec<usize> = stat.cpu.keys().cloned().collect();
+    let hotplug = get_cpu_hotplug_events(time, &old_cpus, &new_cpus);
+    for e in &hotplug {
+        info!("CPU{:02} is {}.", e.id, if e.online { "online" } else { "offline" });
+    }
+    collect_cpu_idle_info(&mut stat.idle, &mut cpu_info)?;
+    let hwmon = collect_sensor_readings(&stat.sensors)?;
+    let sensor = get_sensor_info(&stat.profile, &hwmon);
+    let thermal = collect_thermal_info(&stat.thermal);
     let hdd_temp = get_nvme_hdd_temp()?;
+    let throttle_counts = get_throttle_counts(&new_cpus);
+    let power = collect_power_info(&mut stat.rapl)?;
+    let memory = collect_memory_info(&mut stat.vm)?;
+    let disks = collect_disk_io_info(&mut stat.disk)?;
+    let net = collect_net_info(&mut stat.net)?;
+    let pressure = collect_pressure_info(&mut stat.pressure)?;
+    let sched = collect_sched_info(&mut stat.sched)?;
+    let process = stat.process.as_mut().and_then(collect_process_info);
+    if process.is_none() {
+        if let Some(p) = stat.process.take() {
+            info!("Process {} has exited.", p.name);
+        }
+    }
+    let cgroup = match stat.cgroup.as_mut().map(collect_cgroup_info) {
+        Some(Ok(c)) => Some(c),
+        Some(Err(e)) => {
+            // The cgroup is gone, e.g. its container has stopped
+            for t in e.iter() { warn!("Collect cgroup info failed: {}", t); }
+            stat.cgroup = None;
+            None
+        },
+        None => None,
+    };
     Ok(PemonEntry {
-        cpu_info: cpu_info,
-        sensor: sensor,
-        hdd_temp: hdd_temp,
+        time,
+        interval,
+        cpu_info,
+        sensor,
+        hdd_temp,
+        throttle_counts,
+        hotplug,
+        power,
+        memory,
+        disks,
+        net,
+        pressure,
+        sched,
+        process,
+        cgroup,
+        thermal,
+        hwmon,
     })
 }
 
-fn do_cpu_statistic(pemon: &Vec<PemonEntry>) -> String {
-    let mut sum = Vec::new();
-    let mut min = Vec::new();
-    let mut max = Vec::new();
-    let mut l1 = Vec::new();
-    let mut l2 = Vec::new();
-    let mut l3 = Vec::new();
-    let mut l4 = Vec::new();
-    let mut l5 = Vec::new();
-    let mut ret = String::new();
-    let len = pemon.len();
-    let count = pemon[0].cpu_info.len();
-    for _ in 0..count {
-        sum.push(0.0);
-        min.push(std::f64::MAX);
-        max.push(0.0);
-        l1.push(0);
-        l2.push(0);
-        l3.push(0);
-        l4.push(0);
-        l5.push(0);
-    }
-
-    for i in 0..len {
-        let vinfo = &pemon[i].cpu_info;
-        for j in 0..vinfo.len() {
-            let freq = vinfo[j].freq;
-            sum[j] += freq;
-            if freq < min[j] {
-                min[j] = freq;
-            }
-            if freq > max[j] {
-                max[j] = freq;
-            }
-            if freq < 3600.0 {
-               l1[j] += 1;
-            }
-            if freq >= 3600.0 && freq < 4000.0{
-               l2[j] += 1;
-            }
-            if freq >= 4000.0 && freq < 4100.0{
-               l3[j] += 1;
-            }
-            if freq >= 4100.0 && freq < 4250.0{
-               l4[j] += 1;
-            }
-            if freq >= 4250.0 {
-               l5[j] += 1;
-            }
-        }
+// Average over the CPUs of a group which were online in this sample
+fn group_avg(cpu_info: &[CpuInfoEntry], cpus: &[usize], value: fn(&CpuInfoEntry) -> f64) -> Option<f64> {
+    let v: Vec<f64> = cpu_info.iter().filter(|c| cpus.contains(&c.id)).map(value).collect();
+    if v.is_empty() {
+        None
+    } else {
+        Some(v.iter().sum::<f64>() / v.len() as f64)
     }
-    for i in 0..count {
-        let avg = sum[i] / len as f64;
-        let r1 = l1[i] as f64 / len as f64 * 100.0;
-        let r2 = l2[i] as f64 / len as f64 * 100.0;
-        let r3 = l3[i] as f64 / len as f64 * 100.0;
-        let r4 = l4[i] as f64 / len as f64 * 100.0;
-        let r5 = l5[i] as f64 / len as f64 * 100.0;
-        let seq;
-        if i < 10 {
-            seq = format!("0{}", i);
-        } else {
-            seq = format!("{}", i);
-        }
-        if ret.len() == 0 {
-            ret = format!("CPU{} frequency:\tavg: {:.2} | min: {:.2} | max: {:.2} | <3.6GHz: {:.2}% | 3.6-4.0GHz: {:.2}% | 4.0-4.1GHz: {:.2}% | 4.1-4.25GHz: {:.2}% | >=4.25GHz: {:.2}%",
-                    seq, avg, min[i], max[i], r1, r2, r3, r4, r5);
-        } else {
-            ret = format!("{}\n{}", ret, format!("CPU{} frequency:\tavg: {:.2} | min: {:.2} | max: {:.2} | <3.6GHz: {:.2}% | 3.6-4.0GHz: {:.2}% | 4.0-4.1GHz: {:.2}% | 4.1-4.25GHz: {:.2}% | >=4.25GHz: {:.2}%",
-                                         seq, avg, min[i], max[i], r1, r2, r3, r4, r5));
-        }
-    }
-
-    sum.clear(); min.clear(); max.clear(); l1.clear(); l2.clear(); l3.clear(); l4.clear(); l5.clear();
-    for _ in 0..count {
-        sum.push(0.0);
-        min.push(std::f64::MAX);
-        max.push(0.0);
-        l1.push(0);
-        l2.push(0);
-        l3.push(0);
-        l4.push(0);
-        l5.push(0);
-    }
-    for i in 0..len {
-        let vinfo = &pemon[i].cpu_info;
-        for j in 0..vinfo.len() {
-            let usage = vinfo[j].usage;
-            sum[j] += usage;
-            if usage < min[j] {
-                min[j] = usage;
-            }
-            if usage > max[j] {
-                max[j] = usage;
-            }
-            if usage < 10.0 {
-               l1[j] += 1;
-            }
-            if usage >= 10.0 && usage < 50.0{
-               l2[j] += 1;
-            }
-            if usage >= 50.0 && usage < 70.0{
-               l3[j] += 1;
-            }
-            if usage >= 70.0 && usage < 90.0{
-               l4[j] += 1;
-            }
-            if usage >= 90.0 {
-               l5[j] += 1;
-            }
+}
+
+fn do_cpu_statistic(pemon: &[PemonEntry], groups: &[CpuGroup]) -> String {
+    let mut ret = Vec::new();
+    for g in groups {
+        let freqs: Vec<f64> = pemon.iter().filter_map(|e| group_avg(&e.cpu_info, &g.cpus, |c| c.freq)).collect();
+        if freqs.is_empty() {
+            continue;
         }
+        ret.push(format_stat(&format!("{} frequency:", g.name), &freqs,
+                             &[3600.0, 4000.0, 4100.0, 4250.0],
+                             &["<3.6GHz", "3.6-4.0GHz", "4.0-4.1GHz", "4.1-4.25GHz", ">=4.25GHz"]));
     }
-    for i in 0..count {
-        let avg = sum[i] / len as f64;
-        let r1 = l1[i] as f64 / len as f64 * 100.0;
-        let r2 = l2[i] as f64 / len as f64 * 100.0;
-        let r3 = l3[i] as f64 / len as f64 * 100.0;
-        let r4 = l4[i] as f64 / len as f64 * 100.0;
-        let r5 = l5[i] as f64 / len as f64 * 100.0;
-        let seq;
-        if i < 10 {
-            seq = format!("0{}", i);
-        } else {
-            seq = format!("{}", i);
+    for g in groups {
+        let usages: Vec<f64> = pemon.iter().filter_map(|e| group_avg(&e.cpu_info, &g.cpus, |c| c.usage)).collect();
+        if usages.is_empty() {
+            continue;
         }
-        ret = format!("{}\n{}", ret, format!("CPU{} usage:\t\tavg: {:.2} | min: {:.2} | max: {:.2} | <10%: {:.2}% | 10%-50%: {:.2}% | 50%-70%: {:.2}% | 70%-90%: {:.2}% | >=90%: {:.2}%",
-                                     seq, avg, min[i], max[i], r1, r2, r3, r4, r5));
+        ret.push(format_stat(&format!("{} usage:", g.name), &usages,
+                             &[10.0, 50.0, 70.0, 90.0],
+                             &["<10%", "10%-50%", "50%-70%", "70%-90%", ">=90%"]));
     }
-    ret
+    ret.join("\n")
 }
 
-fn do_sensor_statistic(pemon: &Vec<PemonEntry>) -> String {
-    let mut sum = 0;
-    let mut min = usize::max_value();
-    let mut max = 0;
-    let mut l1 = 0;
-    let mut l2 = 0;
-    let mut l3 = 0;
-    let mut l4 = 0;
-    let mut l5 = 0;
-    let mut ret;
-    let len = pemon.len();
-    for i in 0..len {
-        let temp = pemon[i].sensor.cpu_temp;
-        sum += temp;
-        if temp < min {
-            min = temp;
-        }
-        if temp > max {
-            max = temp;
-        }
-        if temp < 40 {
-            l1 += 1;
-        }
-        if temp >= 40 && temp < 60 {
-            l2 += 1;
-        }
-        if temp >=60 && temp < 70 {
-            l3 += 1;
-        }
-        if temp >=70 && temp < 80 {
-            l4 += 1;
-        }
-        if temp >= 80 {
-            l5 += 1;
-        }
-    }
-    let avg = sum as f64 / len as f64;
-    let r1 = l1 as f64 / len as f64 * 100.0;
-    let r2 = l2 as f64 / len as f64 * 100.0;
-    let r3 = l3 as f64 / len as f64 * 100.0;
-    let r4 = l4 as f64 / len as f64 * 100.0;
-    let r5 = l5 as f64 / len as f64 * 100.0;
-    ret = format!("CPU temperature:\tavg: {:.2} | min: {} | max: {} | <40°C: {:.2}% | 40°C-60°C: {:.2}% | 60°C-70°C: {:.2}% | 70°C-80°C: {:.2}% | >=80°C: {:.2}%",
-            avg, min, max, r1, r2, r3, r4, r5);
-
-    sum = 0; min = usize::max_value(); max = 0; l1 = 0; l2 = 0; l3 = 0; l4 = 0; l5 = 0;
-    for i in 0..len {
-        let temp = pemon[i].sensor.mb_temp;
-        sum += temp;
-        if temp < min {
-            min = temp;
-        }
-        if temp > max {
-            max = temp;
-        }
-        if temp < 40 {
-            l1 += 1;
-        }
-        if temp >= 40 && temp < 60 {
-            l2 += 1;
-        }
-        if temp >=60 && temp < 70 {
-            l3 += 1;
-        }
-        if temp >=70 && temp < 80 {
-            l4 += 1;
-        }
-        if temp >= 80 {
-            l5 += 1;
-        }
-    }
-    let avg = sum as f64 / len as f64;
-    let r1 = l1 as f64 / len as f64 * 100.0;
-    let r2 = l2 as f64 / len as f64 * 100.0;
-    let r3 = l3 as f64 / len as f64 * 100.0;
-    let r4 = l4 as f64 / len as f64 * 100.0;
-    let r5 = l5 as f64 / len as f64 * 100.0;
-    ret = format!("{}\n{}", ret,
-          format!("MB temperature:\t\tavg: {:.2} | min: {} | max: {} | <40°C: {:.2}% | 40°C-60°C: {:.2}% | 60°C-70°C: {:.2}% | 70°C-80°C: {:.2}% | >=80°C: {:.2}%",
-          avg, min, max, r1, r2, r3, r4, r5));
-
-    sum = 0; min = usize::max_value(); max = 0; l1 = 0; l2 = 0; l3 = 0; l4 = 0; l5 = 0;
-    for i in 0..len {
-        let temp = pemon[i].sensor.chipset_temp;
-        sum += temp;
-        if temp < min {
-            min = temp;
-        }
-        if temp > max {
-            max = temp;
-        }
-        if temp < 40 {
-            l1 += 1;
-        }
-        if temp >= 40 && temp < 60 {
-            l2 += 1;
-        }
-        if temp >=60 && temp < 70 {
-            l3 += 1;
-        }
-        if temp >=70 && temp < 80 {
-            l4 += 1;
-        }
-        if temp >= 80 {
-            l5 += 1;
-        }
-    }
-    let avg = sum as f64 / len as f64;
-    let r1 = l1 as f64 / len as f64 * 100.0;
-    let r2 = l2 as f64 / len as f64 * 100.0;
-    let r3 = l3 as f64 / len as f64 * 100.0;
-    let r4 = l4 as f64 / len as f64 * 100.0;
-    let r5 = l5 as f64 / len as f64 * 100.0;
-    ret = format!("{}\n{}", ret,
-          format!("Chipset temperature:\tavg: {:.2} | min: {} | max: {} | <40°C: {:.2}% | 40°C-60°C: {:.2}% | 60°C-70°C: {:.2}% | 70°C-80°C: {:.2}% | >=80°C: {:.2}%",
-          avg, min, max, r1, r2, r3, r4, r5));
-
-    sum = 0; min = usize::max_value(); max = 0; l1 = 0; l2 = 0; l3 = 0; l4 = 0; l5 = 0;
-    for i in 0..len {
-        let temp = pemon[i].sensor.cpu_fan_rpm;
-        sum += temp;
-        if temp < min {
-            min = temp;
-        }
-        if temp > max {
-            max = temp;
-        }
-        if temp < 1500 {
-            l1 += 1;
-        }
-        if temp >= 1500 && temp < 2000 {
-            l2 += 1;
-        }
-        if temp >= 2000 && temp < 2500 {
-            l3 += 1;
-        }
-        if temp >= 2500 && temp < 3000 {
-            l4 += 1;
-        }
-        if temp >= 3000 {
-            l5 += 1;
+/// The temperature and fan metrics, which the guard and fan curves are driven by.
+fn get_sensor_metrics(sensor: &Sensor, hdd_temp: Option<f64>, thermal: &ThermalEntry,
+                      hwmon: &[HwmonEntry]) -> BTreeMap<String, f64> {
+    let mut result = BTreeMap::new();
+    // Metrics the board profile doesn't map are left out, rather than read 0
+    for (name, reading) in sensor.metrics().iter() {
+        if let Some(r) = reading {
+            result.insert(name.to_string(), r.value);
         }
     }
-    let avg = sum as f64 / len as f64;
-    let r1 = l1 as f64 / len as f64 * 100.0;
-    let r2 = l2 as f64 / len as f64 * 100.0;
-    let r3 = l3 as f64 / len as f64 * 100.0;
-    let r4 = l4 as f64 / len as f64 * 100.0;
-    let r5 = l5 as f64 / len as f64 * 100.0;
-    ret = format!("{}\n{}", ret,
-          format!("CPU Fan RPM:\t\tavg: {:.2} | min: {} | max: {} | <1500: {:.2}% | 1500-2000: {:.2}% | 2000-2500: {:.2}% | 2500-3000: {:.2}% | >=3000: {:.2}%",
-          avg, min, max, r1, r2, r3, r4, r5));
+    if let Some(t) = hdd_temp {
+        result.insert("hdd_temp".to_string(), t);
+    }
+    for z in &thermal.zones {
+        result.insert(format!("thermal.{}", metric_name(&z.name)), z.temp);
+    }
+    for h in hwmon {
+        result.insert(format!("hwmon.{}", metric_name(&h.name)), h.value);
+    }
+    result
+}
 
-    sum = 0; min = usize::max_value(); max = 0; l1 = 0; l2 = 0; l3 = 0; l4 = 0; l5 = 0;
-    for i in 0..len {
-        let temp = pemon[i].sensor.chassis_fan_rpm;
-        sum += temp;
-        if temp < min {
-            min = temp;
-        }
-        if temp > max {
-            max = temp;
+/// The metrics of one sample which alert rules can refer to, see METRICS.
+/// Fields a sample doesn't have, e.g. the latency of an idle disk, are left out.
+fn get_metric_values(e: &PemonEntry) -> BTreeMap<String, f64> {
+    let mut result = get_sensor_metrics(&e.sensor, Some(e.hdd_temp), &e.thermal, &e.hwmon);
+    let mut add = |name: String, value: Option<f64>| {
+        if let Some(v) = value {
+            result.insert(name, v);
         }
-        if temp < 1500 {
-            l1 += 1;
+    };
+    if !e.cpu_info.is_empty() {
+        let len = e.cpu_info.len() as f64;
+        add("cpu_freq".to_string(), Some(e.cpu_info.iter().map(|c| c.freq).sum::<f64>() / len));
+        add("cpu_usage".to_string(), Some(e.cpu_info.iter().map(|c| c.usage).sum::<f64>() / len));
+    }
+    for c in &e.cpu_info {
+        add(format!("cpu{}.freq", c.id), Some(c.freq));
+        add(format!("cpu{}.usage", c.id), Some(c.usage));
+        for s in &c.idle_states {
+            add(format!("cpu{}.idle.{}", c.id, metric_name(&s.name)), Some(s.residency));
         }
-        if temp >= 1500 && temp < 1800 {
-            l2 += 1;
+    }
+    if let Some(p) = &e.power {
+        add("package_watts".to_string(), Some(p.package_watts()));
+        add("core_watts".to_string(), Some(p.core_watts()));
+        add("dram_watts".to_string(), Some(p.dram_watts()));
+    }
+    let m = &e.memory;
+    for (name, value) in [("memory_total", m.total), ("memory_used", m.used), ("memory_available", m.available),
+                          ("memory_cached", m.cached), ("memory_dirty", m.dirty), ("swap_total", m.swap_total),
+                          ("swap_used", m.swap_used), ("fault_rate", m.fault_rate),
+                          ("major_fault_rate", m.major_fault_rate), ("swapin_rate", m.swapin_rate),
+                          ("swapout_rate", m.swapout_rate)] {
+        add(name.to_string(), Some(value));
+    }
+    let s = &e.sched;
+    for (name, value) in [("load1", s.load1), ("load5", s.load5), ("load15", s.load15),
+                          ("procs_running", s.procs_running as f64), ("procs_blocked", s.procs_blocked as f64),
+                          ("threads", s.threads as f64), ("ctxt_rate", s.ctxt_rate), ("intr_rate", s.intr_rate),
+                          ("fork_rate", s.fork_rate)] {
+        add(name.to_string(), Some(value));
+    }
+    for d in &e.disks {
+        let name = metric_name(&d.name);
+        add(format!("disk.{}.read_mbps", name), Some(d.read_mbps));
+        add(format!("disk.{}.write_mbps", name), Some(d.write_mbps));
+        add(format!("disk.{}.iops", name), Some(d.iops));
+        add(format!("disk.{}.latency", name), d.latency);
+        add(format!("disk.{}.util", name), Some(d.util));
+    }
+    for n in &e.net {
+        let name = metric_name(&n.name);
+        add(format!("net.{}.rx_mbps", name), Some(n.rx_mbps));
+        add(format!("net.{}.tx_mbps", name), Some(n.tx_mbps));
+        add(format!("net.{}.rx_packets", name), Some(n.rx_packets));
+        add(format!("net.{}.tx_packets", name), Some(n.tx_packets));
+        add(format!("net.{}.errors", name), Some(n.errors));
+        add(format!("net.{}.dropped", name), Some(n.dropped));
+    }
+    for p in &e.pressure {
+        let name = metric_name(&p.name);
+        add(format!("pressure.{}.some_avg10", name), Some(p.some_avg10));
+        add(format!("pressure.{}.some_avg60", name), Some(p.some_avg60));
+        add(format!("pressure.{}.some_avg300", name), Some(p.some_avg300));
+        add(format!("pressure.{}.some_stall", name), Some(p.some_stall));
+        add(format!("pressure.{}.full_avg10", name), p.full_avg10);
+        add(format!("pressure.{}.full_avg60", name), p.full_avg60);
+        add(format!("pressure.{}.full_avg300", name), p.full_avg300);
+        add(format!("pressure.{}.full_stall", name), p.full_stall);
+    }
+    for z in &e.thermal.zones {
+        let name = metric_name(&z.name);
+        add(format!("thermal.{}.passive_margin", name), z.passive_margin);
+        add(format!("thermal.{}.critical_margin", name), z.critical_margin);
+    }
+    for c in &e.thermal.cooling {
+        add(format!("cooling.{}.state", metric_name(&c.name)), Some(c.state as f64));
+    }
+    if let Some(p) = &e.process {
+        add("process.processes".to_string(), Some(p.processes as f64));
+        add("process.usage".to_string(), Some(p.usage));
+        add("process.rss".to_string(), Some(p.rss));
+        add("process.threads".to_string(), Some(p.threads as f64));
+        add("process.voluntary_rate".to_string(), Some(p.voluntary_rate));
+        add("process.involuntary_rate".to_string(), Some(p.involuntary_rate));
+        add("process.read_mbps".to_string(), Some(p.read_mbps));
+        add("process.write_mbps".to_string(), Some(p.write_mbps));
+    }
+    if let Some(c) = &e.cgroup {
+        add("cgroup.usage".to_string(), Some(c.usage));
+        add("cgroup.throttled_periods".to_string(), c.throttled_periods);
+        add("cgroup.throttled_ms".to_string(), Some(c.throttled_ms));
+        add("cgroup.memory".to_string(), c.memory);
+        add("cgroup.anon".to_string(), Some(c.anon));
+        add("cgroup.file".to_string(), Some(c.file));
+        add("cgroup.read_mbps".to_string(), Some(c.read_mbps));
+        add("cgroup.write_mbps".to_string(), Some(c.write_mbps));
+        add("cgroup.iops".to_string(), Some(c.iops));
+    }
+    debug_assert!(result.keys().all(|k| is_known_metric(k)), "metric missing from METRICS");
+    result
+}
+
+fn get_run_summary(pemon: &[PemonEntry], runtime: Duration, completed: bool) -> RunSummary {
+    let avg = |values: &[f64]| if values.is_empty() { 0.0 } else { values.iter().sum::<f64>() / values.len() as f64 };
+    let freqs: Vec<f64> = pemon.iter().flat_map(|e| e.cpu_info.iter().map(|c| c.freq)).collect();
+    let temps: Vec<f64> = pemon.iter().filter_map(|e| e.sensor.cpu_temp.map(|r| r.value)).collect();
+    let power: Vec<f64> = pemon.iter().filter_map(|e| e.power.as_ref().map(|p| p.package_watts())).collect();
+    RunSummary {
+        avg_freq: avg(&freqs),
+        avg_temp: if temps.is_empty() { None } else { Some(avg(&temps)) },
+        max_temp: temps.iter().cloned().fold(None, |m: Option<f64>, t| Some(m.map_or(t, |m| m.max(t)))),
+        avg_power: if power.is_empty() { None } else { Some(avg(&power)) },
+        runtime,
+        completed,
+    }
+}
+
+fn do_sensor_statistic(pemon: &[PemonEntry]) -> String {
+    let samples: Vec<(Duration, f64, &Sensor)> = pemon.iter()
+        .map(|e| (e.time, e.interval.as_secs_f64(), &e.sensor))
+        .collect();
+    let temp_bounds = [40.0, 60.0, 70.0, 80.0];
+    let temp_labels = ["<40°C", "40°C-60°C", "60°C-70°C", "70°C-80°C", ">=80°C"];
+    let mut ret = Vec::new();
+    let mut report = |name: &str, f: fn(&Sensor) -> Option<Reading>, bounds: &[f64], labels: &[&str], fan: bool| {
+        let readings: Vec<(Duration, f64, Reading)> = samples.iter()
+            .filter_map(|(t, secs, s)| f(s).map(|r| (*t, *secs, r)))
+            .collect();
+        if readings.is_empty() && !samples.is_empty() {
+            ret.push(format!("{}not mapped by the board profile", title_tabs(&format!("{}:", name))));
+            return;
         }
-        if temp >= 1800 && temp < 2100 {
-            l3 += 1;
-        }
-        if temp >= 2100 && temp < 2500 {
-            l4 += 1;
-        }
-        if temp >= 2500 {
-            l5 += 1;
+        let values: Vec<f64> = readings.iter().map(|(_, _, r)| r.value).collect();
+        ret.push(format_stat(&format!("{}:", name), &values, bounds, labels));
+        // Fans have a minimum, temperatures a max and crit
+        if fan {
+            let mins: Vec<(f64, Option<f64>)> = readings.iter().map(|(_, _, r)| (r.value, r.min)).collect();
+            ret.extend(format_fan_headroom(name, &mins));
+        } else {
+            let limits: Vec<(f64, f64, Option<f64>, Option<f64>)> = readings.iter()
+                .map(|(_, secs, r)| (*secs, r.value, r.max, r.crit))
+                .collect();
+            ret.extend(format_temp_headroom(name, &limits));
         }
-    }
-    let avg = sum as f64 / len as f64;
-    let r1 = l1 as f64 / len as f64 * 100.0;
-    let r2 = l2 as f64 / len as f64 * 100.0;
-    let r3 = l3 as f64 / len as f64 * 100.0;
-    let r4 = l4 as f64 / len as f64 * 100.0;
-    let r5 = l5 as f64 / len as f64 * 100.0;
-    ret = format!("{}\n{}", ret,
-          format!("Chassis Fan RPM:\tavg: {:.2} | min: {} | max: {} | <1500: {:.2}% | 1500-1800: {:.2}% | 1800-2100: {:.2}% | 2100-2500: {:.2}% | >=2500: {:.2}%",
-          avg, min, max, r1, r2, r3, r4, r5));
+        let alarms: Vec<Duration> = readings.iter().filter(|(_, _, r)| r.alarm).map(|(t, _, _)| *t).collect();
+        ret.extend(format_alarms(name, &alarms));
+    };
+    report("CPU temperature", |s| s.cpu_temp, &temp_bounds, &temp_labels, false);
+    report("MB temperature", |s| s.mb_temp, &temp_bounds, &temp_labels, false);
+    report("Chipset temperature", |s| s.chipset_temp, &temp_bounds, &temp_labels, false);
+    report("CPU Fan RPM", |s| s.cpu_fan_rpm, &[1500.0, 2000.0, 2500.0, 3000.0],
+           &["<1500", "1500-2000", "2000-2500", "2500-3000", ">=3000"], true);
+    report("Chassis Fan RPM", |s| s.chassis_fan_rpm, &[1500.0, 1800.0, 2100.0, 2500.0],
+           &["<1500", "1500-1800", "1800-2100", "2100-2500", ">=2500"], true);
+    ret.join("\n")
+}
 
-    ret
+fn do_hdd_temp_statistic(pemon: &[PemonEntry]) -> String {
+    let temps: Vec<f64> = pemon.iter().map(|e| e.hdd_temp).collect();
+    format_stat("HDD temperature:", &temps, &[30.0, 50.0, 70.0], &["<30°C", "30°C-50°C", "50°C-70°C", ">=70°C"])
 }
 
-fn do_hdd_temp_statistic(pemon: &Vec<PemonEntry>) -> String {
-    let mut sum = 0;
-    let mut min = usize::max_value();
-    let mut max = 0;
-    let mut below_30 = 0;
-    let mut t_30_50 = 0;
-    let mut t_50_70 = 0;
-    let mut above_70 = 0;
-    let len = pemon.len();
-    for i in 0..len {
-        let temp = pemon[i].hdd_temp;
-        sum += temp;
-        if temp < min {
-            min = temp;
-        }
-        if temp > max {
-            max = temp;
-        }
-        if temp < 30 {
-            below_30 += 1;
-        }
-        if temp >= 30 && temp < 50 {
-            t_30_50 += 1;
-        }
-        if temp >=50 && temp < 70 {
-            t_50_70 += 1;
-        }
-        if temp >= 70 {
-            above_70 += 1;
-        }
-    }
-    let avg = sum as f64 / len as f64;
-    let ratio_below_30 = below_30 as f64 / len as f64 * 100.0;
-    let ratio_30_50 = t_30_50 as f64 / len as f64 * 100.0;
-    let ratio_50_70 = t_50_70 as f64 / len as f64 * 100.0;
-    let ratio_above_70 = above_70 as f64 / len as f64 * 100.0;
-    format!("HDD temperature:\tavg: {:.2} | min: {} | max: {} | <30°C: {:.2}% | 30°C-50°C: {:.2}% | 50°C-70°C: {:.2}% | >=70°C: {:.2}%",
-            avg, min, max, ratio_below_30, ratio_30_50, ratio_50_70, ratio_above_70)
+fn do_statistic(pemon: &[PemonEntry], groups: &[CpuGroup], watchers: &Watchers,
+                process: Option<&str>, process_timeline: bool, cgroup: Option<&str>) -> String {
+    let throttle = &watchers.throttle;
+    let freqs: Vec<f64> = pemon.iter().flat_map(|e| e.cpu_info.iter().map(|c| c.freq)).collect();
+    let avg_freq = freqs.iter().sum::<f64>() / freqs.len() as f64;
+    let power: Vec<&PowerEntry> = pemon.iter().filter_map(|e| e.power.as_ref()).collect();
+    let cpu_num = pemon.iter().map(|e| e.cpu_info.len()).max().unwrap_or(1);
+
+    let mut ret = vec![do_cpu_statistic(pemon, groups)];
+    let cpu_info: Vec<&[CpuInfoEntry]> = pemon.iter().map(|e| &e.cpu_info[..]).collect();
+    ret.push(do_cpu_idle_statistic(&cpu_info));
+    ret.push(do_sensor_statistic(pemon));
+    let thermal: Vec<(Duration, &ThermalEntry)> = pemon.iter().map(|e| (e.time, &e.thermal)).collect();
+    ret.push(do_thermal_statistic(&thermal));
+    let hwmon: Vec<(Duration, Duration, &[HwmonEntry])> = pemon.iter()
+        .map(|e| (e.time, e.interval, &e.hwmon[..]))
+        .collect();
+    ret.push(do_hwmon_statistic(&hwmon));
+    if let Some(f) = &watchers.fans {
+        ret.push(do_fan_statistic(f));
+    }
+    ret.push(do_hdd_temp_statistic(pemon));
+    let disks: Vec<&[DiskEntry]> = pemon.iter().map(|e| &e.disks[..]).collect();
+    ret.push(do_disk_io_statistic(&disks));
+    ret.push(do_throttle_statistic(throttle));
+    ret.push(do_alert_statistic(&watchers.alerts.events));
+    if let Some(g) = &watchers.guard {
+        ret.push(do_guard_statistic(&g.config, &g.interventions));
+    }
+    if let Some(name) = cgroup {
+        let samples: Vec<(Duration, &CgroupEntry)> = pemon.iter()
+            .filter_map(|e| e.cgroup.as_ref().map(|c| (e.time, c)))
+            .collect();
+        ret.push(do_cgroup_statistic(name, &samples, &throttle.episodes));
+    }
+    let hotplug: Vec<&CpuHotplugEvent> = pemon.iter().flat_map(|e| &e.hotplug).collect();
+    if !hotplug.is_empty() {
+        ret.push(do_cpu_hotplug_statistic(&hotplug));
+    }
+    ret.push(do_power_statistic(&power, avg_freq));
+    let memory: Vec<&MemoryEntry> = pemon.iter().map(|e| &e.memory).collect();
+    ret.push(do_memory_statistic(&memory));
+    let net: Vec<&[NetEntry]> = pemon.iter().map(|e| &e.net[..]).collect();
+    ret.push(do_net_statistic(&net));
+    let pressure: Vec<&[PressureEntry]> = pemon.iter().map(|e| &e.pressure[..]).collect();
+    ret.push(do_pressure_statistic(&pressure));
+    let sched: Vec<&SchedEntry> = pemon.iter().map(|e| &e.sched).collect();
+    ret.push(do_sched_statistic(&sched, cpu_num));
+    if let Some(name) = process {
+        let samples: Vec<(Duration, Option<f64>, &ProcessEntry)> = pemon.iter()
+            .filter_map(|e| e.process.as_ref().map(|p| (e.time, e.sensor.cpu_temp.map(|r| r.value), p)))
+            .collect();
+        ret.push(do_process_statistic(name, &samples, process_timeline));
+    }
+    ret.join("\n")
 }
 
-fn do_statistic(pemon: Vec<PemonEntry>) {
-    println!();
-    println!("{}", do_cpu_statistic(&pemon));
-    println!("{}", do_sensor_statistic(&pemon));
-    println!("{}", do_hdd_temp_statistic(&pemon));
+// None without --guard-pid
+fn get_guard_config(matches: &clap::ArgMatches) -> Option<Result<GuardConfig>> {
+    let pid = matches.value_of("guard-pid")?;
+    let parse = |name: &str| -> Result<Option<f64>> {
+        match matches.value_of(name) {
+            Some(s) => match s.parse::<f64>() {
+                Ok(v) if v.is_finite() => Ok(Some(v)),
+                _ => bail!("Invalid --{}: {}", name, s),
+            },
+            None => Ok(None),
+        }
+    };
+    Some((|| {
+        let pid = match pid.parse::<i32>() {
+            Ok(p) if p > 0 => p,
+            _ => bail!("Invalid guard pid: {}", pid),
+        };
+        let critical = parse("guard-temp")?.unwrap_or(DEFAULT_GUARD_TEMP);
+        Ok(GuardConfig {
+            target: if matches.is_present("guard-group") { GuardTarget::Group(pid) } else { GuardTarget::Pid(pid) },
+            metric: matches.value_of("guard-metric").unwrap_or("cpu_temp").to_string(),
+            critical,
+            resume: parse("guard-resume")?.unwrap_or(critical - 10.0),
+            grace: match parse("guard-grace")? {
+                Some(g) => match Duration::try_from_secs_f64(g) {
+                    Ok(d) => Some(d),
+                    Err(_) => bail!("Invalid --guard-grace: {}", g),
+                },
+                None => None,
+            },
+        })
+    })())
 }
 
 fn main() {
@@ -434,11 +687,37 @@ fn main() {
     log::set_max_level(LevelFilter::Debug);
 
     let mut itv = DEFAULT_INTERVAL;
+    let mut temp_limit = DEFAULT_TEMP_LIMIT;
+    let mut report_level = ReportLevel::Thread;
     let matches = App::new("pemon")
                         .version("0.1.0")
                         .author("Mark Zhang <ace119@163.com>")
                         .about("A simple utility to collect frequencies and temperatures.")
-                        .args_from_usage("-i, --interval=[seconds] 'Seconds delayed before next collection, default: 3 seconds'")
+                        .args_from_usage("-i, --interval=[seconds] 'Seconds delayed before next collection, default: 3 seconds'
+                                          -t, --temp-limit=[celsius] 'CPU temperature limit used to detect thermal throttling, default: 95'
+                                          -r, --report-level=[level] 'Group CPUs in the report by thread, core, ccx, die, node or package, default: thread'
+                                          --disk-partitions 'Report disk I/O of partitions too'
+                                          --net-include=[patterns] 'Comma separated network interface patterns to report, default: all'
+                                          --net-exclude=[patterns] 'Comma separated network interface patterns to skip, default: lo,veth*'
+                                          --cgroup=[path] 'Also monitor this cgroup v2, relative to the cgroup2 mount'
+                                          --pid=[pid] 'Also report CPU, memory and I/O usage of this process'
+                                          --pid-tree 'Include the descendants of --pid'
+                                          --pid-timeline 'List --pid usage and temperature of every sample in the report'
+                                          --profile=[file] 'Board profile mapping sensors to metrics, default: selected by DMI board name'
+                                          --sensors-backend=[backend] 'Read sensors from hwmon or lm-sensors, default: hwmon'
+                                          --config=[file] 'Config file with the alert rules, actions and fan curves, re-read on SIGHUP'
+                                          --guard-pid=[pid] 'Pause this process when overheating, resume it once cooled down'
+                                          --guard-group 'Treat --guard-pid as a process group id'
+                                          --guard-metric=[metric] 'Sensor metric which --guard-pid is guarded by, default: cpu_temp'
+                                          --guard-temp=[celsius] 'Pause the guarded process at this value, default: 90'
+                                          --guard-resume=[celsius] 'Resume the guarded process below this value, default: 10 below --guard-temp'
+                                          --guard-grace=[seconds] 'Terminate the guarded process once paused this long, default: never'")
+                        .subcommand(SubCommand::with_name("sweep")
+                                    .about("Run a workload once per cpufreq governor and EPP, and compare the runs.")
+                                    .setting(AppSettings::TrailingVarArg)
+                                    .args_from_usage("--governors=<list> 'Comma separated cpufreq governors to run the workload with'
+                                                      --epp=[list] 'Comma separated energy_performance_preference values to run the workload with too'
+                                                      <cmd>... 'The workload command and its arguments, after --'"))
                         .get_matches();
 
     if let Some(s) = matches.value_of("interval") {
@@ -446,6 +725,17 @@ fn main() {
             itv = us;
         }
     }
+    if let Some(s) = matches.value_of("temp-limit") {
+        if let Ok(t) = s.parse::<f64>() {
+            temp_limit = t;
+        }
+    }
+    if let Some(s) = matches.value_of("report-level") {
+        match ReportLevel::from_name(s) {
+            Some(l) => report_level = l,
+            None => warn!("Unknown report level: {}, report per thread.", s),
+        }
+    }
 
     let user = env::var("USER").unwrap();
     debug!("user is: {}", user);
@@ -463,46 +753,329 @@ fn main() {
         },
     }
 
-    let cpu_num = match get_cpu_num() {
+    match matches.subcommand_matches("sweep") {
+        Some(sweep) => run_sweep(&matches, sweep, itv, temp_limit, report_level),
+        None => {
+            monitor(&matches, itv, temp_limit, report_level, None);
+        },
+    }
+}
+
+fn run_sweep(matches: &ArgMatches, sweep: &ArgMatches, itv: u64, temp_limit: f64, report_level: ReportLevel) {
+    let split = |name: &str| -> Vec<String> {
+        sweep.value_of(name).unwrap_or("").split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect()
+    };
+    let governors = split("governors");
+    let epps = split("epp");
+    let cmd: Vec<String> = sweep.values_of("cmd").map(|v| v.map(|s| s.to_string()).collect()).unwrap_or_default();
+    if governors.is_empty() || cmd.is_empty() {
+        error!("A sweep needs at least one governor and a workload command.");
+        return;
+    }
+    // Restored when dropped, so also on an error or a panic
+    let mut settings = match save_governors() {
         Ok(o) => o,
         Err(e) => {
-            for t in e.iter() { error!("Get CPU number failed: {}", t); }
+            for t in e.iter() { error!("Save CPU governors failed: {}", t); }
             return;
         },
     };
-    info!("CPU number: {}", cpu_num);
+    if let Err(e) = settings.check(&governors, &epps) {
+        for t in e.iter() { error!("Check CPU governors failed: {}", t); }
+        return;
+    }
+    let (runs, skipped) = get_sweep_runs(&governors, &epps);
+    for (governor, epp) in &skipped {
+        warn!("Skip governor {} with EPP {}: the governor only takes EPP performance.", governor, epp);
+    }
+    let mut results = Vec::new();
+    for (i, (governor, epp)) in runs.iter().enumerate() {
+        if QUIT.load(Ordering::SeqCst) {
+            break;
+        }
+        println!();
+        println!("{}governor: {} | EPP: {}", title_tabs(&format!("Sweep run {}/{}:", i + 1, runs.len())),
+                 governor, epp.as_deref().unwrap_or("-"));
+        if let Err(e) = settings.apply(governor, epp.as_deref()) {
+            for t in e.iter() { error!("Set CPU governor failed: {}", t); }
+            continue;
+        }
+        match monitor(matches, itv, temp_limit, report_level, Some(&cmd)) {
+            Some(summary) => results.push(SweepResult { governor: governor.clone(), epp: epp.clone(), summary }),
+            None => break,
+        }
+    }
+    settings.restore();
+    println!();
+    println!("{}", do_sweep_statistic(&results, &skipped));
+}
+
+/// Monitor until told to quit or, if given, until the workload exits, then
+/// print the report.
+fn monitor(matches: &ArgMatches, itv: u64, temp_limit: f64, report_level: ReportLevel,
+           workload: Option<&[String]>) -> Option<RunSummary> {
+    let cpus = match get_online_cpus() {
+        Ok(o) => o,
+        Err(e) => {
+            for t in e.iter() { error!("Get online CPUs failed: {}", t); }
+            return None;
+        },
+    };
+    info!("CPU number: {}", cpus.len());
+    // CPUs which are offline now may come online during the run
+    let present = get_present_cpus().unwrap_or_else(|_| cpus.clone());
+    let topology = get_cpu_topology(&present);
+    let groups = get_cpu_groups(&topology, report_level);
 
     info!("Initialize CPU stats...");
-    let mut cpu_stats = match initial_cpu_stats(cpu_num) {
+    let cpu_stats = match initial_cpu_stats() {
         Ok(o) => o,
         Err(e) => {
             for t in e.iter() { error!("Initial cpu stats failed: {}", t); }
-            return;
+            return None;
+        },
+    };
+    let rapl = match initial_rapl_stat() {
+        Ok(o) => o,
+        Err(e) => {
+            for t in e.iter() { error!("Initial RAPL stats failed: {}", t); }
+            return None;
+        },
+    };
+    let idle = match initial_cpu_idle_stat(&cpus) {
+        Ok(o) => o,
+        Err(e) => {
+            for t in e.iter() { error!("Initial cpuidle stats failed: {}", t); }
+            return None;
+        },
+    };
+    let vm = match initial_vm_stat() {
+        Ok(o) => o,
+        Err(e) => {
+            for t in e.iter() { error!("Initial vmstat failed: {}", t); }
+            return None;
+        },
+    };
+    let disk = match initial_disk_io_stat(matches.is_present("disk-partitions")) {
+        Ok(o) => o,
+        Err(e) => {
+            for t in e.iter() { error!("Initial diskstats failed: {}", t); }
+            return None;
+        },
+    };
+    let filter = NetFilter::new(matches.value_of("net-include").unwrap_or(""),
+                                matches.value_of("net-exclude").unwrap_or(DEFAULT_NET_EXCLUDE));
+    let net = match initial_net_dev_stat(filter) {
+        Ok(o) => o,
+        Err(e) => {
+            for t in e.iter() { error!("Initial network stats failed: {}", t); }
+            return None;
+        },
+    };
+    let cgroup_dir = matches.value_of("cgroup").map(get_cgroup_path);
+    let pressure = initial_pressure_stat(cgroup_dir.as_deref());
+    let cgroup = match cgroup_dir.as_deref().map(initial_cgroup_stat) {
+        Some(Ok(o)) => Some(o),
+        Some(Err(e)) => {
+            for t in e.iter() { error!("Initial cgroup stats failed: {}", t); }
+            return None;
+        },
+        None => None,
+    };
+    let sched = match initial_sched_stat() {
+        Ok(o) => o,
+        Err(e) => {
+            for t in e.iter() { error!("Initial scheduler stats failed: {}", t); }
+            return None;
+        },
+    };
+    let process = match matches.value_of("pid").map(|s| s.parse::<usize>()) {
+        None => None,
+        Some(Ok(pid)) => match initial_process_stat(pid, matches.is_present("pid-tree")) {
+            Ok(o) => Some(o),
+            Err(e) => {
+                for t in e.iter() { error!("Initial process stats failed: {}", t); }
+                return None;
+            },
+        },
+        Some(Err(_)) => {
+            error!("Invalid pid: {}", matches.value_of("pid").unwrap_or(""));
+            return None;
+        },
+    };
+    let process_name = process.as_ref().map(|p| p.name.clone());
+    let profile = match matches.value_of("profile") {
+        Some(file) => match load_profile(Path::new(file)) {
+            Ok(o) => o,
+            Err(e) => {
+                for t in e.iter() { error!("Load board profile failed: {}", t); }
+                return None;
+            },
+        },
+        None => select_profile(get_board_name().as_deref()),
+    };
+    info!("Board profile: {}", profile.name);
+    let sensors = match SensorBackend::from_name(matches.value_of("sensors-backend").unwrap_or("hwmon")) {
+        Some(b) => b,
+        None => {
+            error!("Unknown sensors backend: {}", matches.value_of("sensors-backend").unwrap_or(""));
+            return None;
+        },
+    };
+    let readings = match collect_sensor_readings(&sensors) {
+        Ok(o) => o,
+        Err(e) => {
+            for t in e.iter() { error!("Read sensors failed: {}", t); }
+            return None;
+        },
+    };
+    let sensor = get_sensor_info(&profile, &readings);
+    let unmapped = sensor.unmapped();
+    if !unmapped.is_empty() {
+        warn!("Board profile {} maps no sensor to {}, they are left out of the report, alerts and fan curves.",
+              profile.name, unmapped.join(", "));
+    }
+    let thermal = initial_thermal_stat();
+    // The guard and fan curves refuse metrics which no sensor backs
+    let sensor_metrics = get_sensor_metrics(&sensor, get_nvme_hdd_temp().ok(), &collect_thermal_info(&thermal),
+                                            &readings);
+    let mut stat = PemonStat {
+        start: Instant::now(),
+        last: Duration::from_secs(0),
+        cpu: cpu_stats,
+        rapl,
+        idle,
+        vm,
+        disk,
+        net,
+        pressure,
+        sched,
+        process,
+        cgroup,
+        thermal,
+        sensors,
+        profile,
+    };
+    let mut config = match matches.value_of("config") {
+        Some(file) => match load_config(Path::new(file)) {
+            Ok(o) => o,
+            Err(e) => {
+                for t in e.iter() { error!("Load config failed: {}", t); }
+                return None;
+            },
+        },
+        None => Config::default(),
+    };
+    let alerts = AlertMonitor::new(config.alerts.clone());
+    let guard = match get_guard_config(matches).map(|c| c.and_then(|c| Guard::new(c, &sensor_metrics))) {
+        None => None,
+        Some(Ok(g)) => Some(g),
+        Some(Err(e)) => {
+            for t in e.iter() { error!("Initial guard failed: {}", t); }
+            return None;
+        },
+    };
+    let actions = config.action.clone().map(ActionRunner::new);
+    let fans = if config.fans.is_empty() {
+        None
+    } else {
+        match initial_fan_controller(&config.fans, &sensor_metrics) {
+            Ok(o) => Some(o),
+            Err(e) => {
+                for t in e.iter() { error!("Initial fan control failed: {}", t); }
+                return None;
+            },
+        }
+    };
+    let throttle = ThrottleDetector::new(get_base_freqs(&present), config.temp_limit.unwrap_or(temp_limit),
+                                        get_throttle_counts(&cpus));
+    let mut watchers = Watchers { throttle, alerts, actions, guard, fans };
+    let mut workload = match workload.map(Workload::spawn) {
+        None => None,
+        Some(Ok(w)) => Some(w),
+        Some(Err(e)) => {
+            for t in e.iter() { error!("Start the workload failed: {}", t); }
+            return None;
         },
     };
-    thread::sleep(Duration::from_secs(itv));
-
     let mut pemon = Vec::new();
+    let mut next = Instant::now() + Duration::from_secs(config.interval.unwrap_or(itv));
     loop {
-        let entry = match collect(&mut cpu_stats) {
+        if !wait_until(next, workload.as_mut()) {
+            let (report, reset, reload) = take_requests();
+            if reload {
+                match matches.value_of("config").map(|f| load_config(Path::new(f))) {
+                    None => warn!("SIGHUP: there is no config file to reload, it is given with --config."),
+                    Some(Err(e)) => {
+                        for t in e.iter() { error!("Reload config failed, keep the current one: {}", t); }
+                    },
+                    Some(Ok(mut c)) => {
+                        if c.fans != config.fans {
+                            warn!("Fan curves are only read at start, restart pemon to change them.");
+                            c.fans = config.fans.clone();
+                        }
+                        watchers.reload(&c, c.temp_limit.unwrap_or(temp_limit), stat.start.elapsed());
+                        config = c;
+                        info!("Config is reloaded, interval: {}s.", config.interval.unwrap_or(itv));
+                    },
+                }
+            }
+            if report {
+                if pemon.is_empty() {
+                    info!("SIGUSR1: no sample collected yet.");
+                } else {
+                    info!("SIGUSR1: report {} samples so far...", pemon.len());
+                    output_report(&do_statistic(&pemon, &groups, &watchers, process_name.as_deref(),
+                                                matches.is_present("pid-timeline"), matches.value_of("cgroup")),
+                                  config.report_file.as_deref());
+                }
+            }
+            if reset {
+                let time = stat.start.elapsed();
+                info!("SIGUSR2: reset the statistic at {}.", format_elapsed(time));
+                pemon.clear();
+                // The next sample only stands for the time since the reset
+                stat.last = time;
+                watchers.reset(time);
+            }
+            continue;
+        }
+
+        let entry = match collect(&mut stat) {
             Ok(o) => o,
             Err(e) => {
                 for t in e.iter() { error!("Collect performance info failed: {}", t); }
                 break;
             },
         };
+        let result = watchers.update(&entry);
         pemon.push(entry);
+        if let Err(e) = result {
+            for t in e.iter() { error!("Update watchers failed: {}", t); }
+            break;
+        }
 
-        unsafe {
-            if QUIT {
-                info!("Pemon is terminating...");
-                break;
-            }
+        if QUIT.load(Ordering::SeqCst) {
+            info!("Pemon is terminating...");
+            break;
+        }
+        if workload.as_mut().is_some_and(|w| w.exited()) {
+            info!("The workload has exited.");
+            break;
         }
 
-        thread::sleep(Duration::from_secs(itv));
+        next = Instant::now() + Duration::from_secs(config.interval.unwrap_or(itv));
     }
+    let (runtime, completed) = match workload.as_mut() {
+        Some(w) => w.finish(),
+        None => (stat.start.elapsed(), true),
+    };
 
+    watchers.finish(stat.start.elapsed());
     info!("Start doing the statistic...");
-    do_statistic(pemon);
+    let summary = get_run_summary(&pemon, runtime, completed);
+    output_report(&do_statistic(&pemon, &groups, &watchers, process_name.as_deref(),
+                                matches.is_present("pid-timeline"), matches.value_of("cgroup")),
+                  config.report_file.as_deref());
+    Some(summary)
 }
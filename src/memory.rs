@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Instant;
+use crate::errors::*;
+use crate::parser::*;
+use crate::statistic::*;
+
+const MEMINFO_FILE: &str = "/proc/meminfo";
+const VMSTAT_FILE: &str = "/proc/vmstat";
+const VMSTAT_KEYS: [&str; 4] = ["pgfault", "pgmajfault", "pswpin", "pswpout"];
+
+pub struct VmStat {
+    counters: BTreeMap<String, u64>,
+    last_time: Instant,
+}
+
+/// Memory sizes are in MiB, rates are per second.
+#[derive(PartialEq, Debug)]
+pub struct MemoryEntry {
+    pub total: f64,
+    pub used: f64,
+    pub available: f64,
+    pub cached: f64,
+    pub dirty: f64,
+    pub swap_total: f64,
+    pub swap_used: f64,
+    pub fault_rate: f64,
+    pub major_fault_rate: f64,
+    pub swapin_rate: f64,
+    pub swapout_rate: f64,
+}
+
+fn read_vmstat() -> Result<BTreeMap<String, u64>> {
+    let mut result = parse_key_values(VMSTAT_FILE, &fs::read_to_string(VMSTAT_FILE)?)?;
+    result.retain(|k, _| VMSTAT_KEYS.contains(&k.as_str()));
+    Ok(result)
+}
+
+fn get_memory_entry(meminfo: &BTreeMap<String, u64>, vmstat: &BTreeMap<String, u64>,
+                    old: &BTreeMap<String, u64>, secs: f64) -> MemoryEntry {
+    let mib = |k: &str| meminfo.get(k).cloned().unwrap_or(0) as f64 / 1024.0;
+    let rate = |k: &str| {
+        let new = vmstat.get(k).cloned().unwrap_or(0);
+        let old = old.get(k).cloned().unwrap_or(new);
+        new.saturating_sub(old) as f64 / secs
+    };
+    MemoryEntry {
+        total: mib("MemTotal"),
+        used: mib("MemTotal") - mib("MemAvailable"),
+        available: mib("MemAvailable"),
+        cached: mib("Cached"),
+        dirty: mib("Dirty"),
+        swap_total: mib("SwapTotal"),
+        swap_used: mib("SwapTotal") - mib("SwapFree"),
+        fault_rate: rate("pgfault"),
+        major_fault_rate: rate("pgmajfault"),
+        swapin_rate: rate("pswpin"),
+        swapout_rate: rate("pswpout"),
+    }
+}
+
+pub fn initial_vm_stat() -> Result<VmStat> {
+    Ok(VmStat { counters: read_vmstat()?, last_time: Instant::now() })
+}
+
+pub fn collect_memory_info(stat: &mut VmStat) -> Result<MemoryEntry> {
+    let meminfo = parse_key_values(MEMINFO_FILE, &fs::read_to_string(MEMINFO_FILE)?)?;
+    let vmstat = read_vmstat()?;
+    let now = Instant::now();
+    let secs = now.duration_since(stat.last_time).as_secs_f64();
+    let result = get_memory_entry(&meminfo, &vmstat, &stat.counters, secs);
+    stat.counters = vmstat;
+    stat.last_time = now;
+    Ok(result)
+}
+
+pub fn do_memory_statistic(memory: &[&MemoryEntry]) -> String {
+    let values = |f: fn(&MemoryEntry) -> f64| -> Vec<f64> { memory.iter().map(|m| f(m)).collect() };
+    let mut ret = vec![
+        format_stat("Memory used:", &values(|m| m.used / m.total * 100.0),
+                    &[25.0, 50.0, 75.0, 90.0], &["<25%", "25%-50%", "50%-75%", "75%-90%", ">=90%"]),
+        format_stat("Memory available MiB:", &values(|m| m.available),
+                    &[512.0, 1024.0, 4096.0], &["<512", "512-1024", "1024-4096", ">=4096"]),
+        format_stat("Memory cached MiB:", &values(|m| m.cached),
+                    &[512.0, 1024.0, 4096.0], &["<512", "512-1024", "1024-4096", ">=4096"]),
+        format_stat("Memory dirty MiB:", &values(|m| m.dirty),
+                    &[16.0, 64.0, 256.0], &["<16", "16-64", "64-256", ">=256"]),
+    ];
+    if memory.iter().any(|m| m.swap_total > 0.0) {
+        ret.push(format_stat("Swap used MiB:", &values(|m| m.swap_used),
+                             &[1.0, 256.0, 1024.0], &["<1", "1-256", "256-1024", ">=1024"]));
+    }
+    ret.push(format_stat("Page faults/s:", &values(|m| m.fault_rate),
+                         &[1000.0, 10000.0, 100000.0], &["<1k", "1k-10k", "10k-100k", ">=100k"]));
+    ret.push(format_stat("Major faults/s:", &values(|m| m.major_fault_rate),
+                         &[1.0, 10.0, 100.0], &["<1", "1-10", "10-100", ">=100"]));
+    ret.push(format_stat("Swap-in pages/s:", &values(|m| m.swapin_rate),
+                         &[1.0, 100.0, 1000.0], &["<1", "1-100", "100-1000", ">=1000"]));
+    ret.push(format_stat("Swap-out pages/s:", &values(|m| m.swapout_rate),
+                         &[1.0, 100.0, 1000.0], &["<1", "1-100", "100-1000", ">=1000"]));
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_memory_entry() {
+        let meminfo = parse_key_values(MEMINFO_FILE, "MemTotal: 8388608 kB\nMemAvailable: 2097152 kB\n\
+                                                      Cached: 1048576 kB\nDirty: 1024 kB\n\
+                                                      SwapTotal: 1048576 kB\nSwapFree: 786432 kB\n").unwrap();
+        let old = parse_key_values(VMSTAT_FILE, "pgfault 1000\npgmajfault 10\npswpin 0\npswpout 0\n").unwrap();
+        let new = parse_key_values(VMSTAT_FILE, "pgfault 7000\npgmajfault 16\npswpin 0\npswpout 30\n").unwrap();
+        let m = get_memory_entry(&meminfo, &new, &old, 3.0);
+        assert_eq!(m.used, 6144.0);
+        assert_eq!(m.swap_used, 256.0);
+        assert_eq!(m.fault_rate, 2000.0);
+        assert_eq!(m.major_fault_rate, 2.0);
+        assert_eq!(m.swapout_rate, 10.0);
+        assert!(do_memory_statistic(&[&m]).starts_with("Memory used:\t\tavg: 75.00 | min: 75.00 | max: 75.00"));
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+use crate::errors::*;
+use crate::parser::*;
+use crate::statistic::*;
+use crate::util::*;
+
+const NET_DEV_FILE: &str = "/proc/net/dev";
+const NET_SYS_DIR: &str = "/sys/class/net";
+pub const DEFAULT_NET_EXCLUDE: &str = "lo,veth*";
+
+/// Which interfaces are reported. An empty include list means all of them.
+pub struct NetFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+pub struct NetDevIoStat {
+    devs: BTreeMap<String, NetDevStat>,
+    filter: NetFilter,
+    last_time: Instant,
+}
+
+/// Rates are per second.
+#[derive(PartialEq, Debug)]
+pub struct NetEntry {
+    pub name: String,
+    pub rx_mbps: f64,
+    pub tx_mbps: f64,
+    pub rx_packets: f64,
+    pub tx_packets: f64,
+    pub errors: f64,
+    pub dropped: f64,
+}
+
+impl NetFilter {
+    /// Build a filter from comma separated pattern lists.
+    pub fn new(include: &str, exclude: &str) -> NetFilter {
+        let split = |s: &str| s.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect();
+        NetFilter { include: split(include), exclude: split(exclude) }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| wildcard_match(p, name)))
+            && !self.exclude.iter().any(|p| wildcard_match(p, name))
+    }
+}
+
+fn read_sys_net_stat(dir: &Path) -> Result<NetDevStat> {
+    let read = |name: &str| read_sysfs_u64(&dir.join("statistics").join(name));
+    Ok(NetDevStat {
+        name: dir.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        rx_bytes: read("rx_bytes")?,
+        rx_packets: read("rx_packets")?,
+        rx_errors: read("rx_errors")?,
+        rx_dropped: read("rx_dropped")?,
+        tx_bytes: read("tx_bytes")?,
+        tx_packets: read("tx_packets")?,
+        tx_errors: read("tx_errors")?,
+        tx_dropped: read("tx_dropped")?,
+    })
+}
+
+// The same counters from sysfs, for systems without /proc/net/dev (e.g. a
+// process in another network namespace). Entries without readable
+// statistics, like the bonding_masters file, are skipped.
+fn read_sys_net_stats(root: &Path) -> Result<Vec<NetDevStat>> {
+    let mut result = Vec::new();
+    for entry in fs::read_dir(root)?.filter_map(|e| e.ok()) {
+        match read_sys_net_stat(&entry.path()) {
+            Ok(s) => result.push(s),
+            Err(e) => debug!("Skip {}: {}", entry.path().display(), e),
+        }
+    }
+    Ok(result)
+}
+
+fn read_net_stats(filter: &NetFilter) -> Result<BTreeMap<String, NetDevStat>> {
+    let stats = match fs::read_to_string(NET_DEV_FILE) {
+        Ok(contents) => parse_net_dev(NET_DEV_FILE, &contents)?,
+        Err(_) => read_sys_net_stats(Path::new(NET_SYS_DIR))?,
+    };
+    Ok(stats.into_iter()
+        .filter(|d| filter.matches(&d.name))
+        .map(|d| (d.name.clone(), d))
+        .collect())
+}
+
+fn get_net_entry(old: &NetDevStat, new: &NetDevStat, secs: f64) -> NetEntry {
+    let rate = |new: u64, old: u64| new.saturating_sub(old) as f64 / secs;
+    NetEntry {
+        name: new.name.clone(),
+        rx_mbps: rate(new.rx_bytes, old.rx_bytes) / 1024.0 / 1024.0,
+        tx_mbps: rate(new.tx_bytes, old.tx_bytes) / 1024.0 / 1024.0,
+        rx_packets: rate(new.rx_packets, old.rx_packets),
+        tx_packets: rate(new.tx_packets, old.tx_packets),
+        errors: rate(new.rx_errors, old.rx_errors) + rate(new.tx_errors, old.tx_errors),
+        dropped: rate(new.rx_dropped, old.rx_dropped) + rate(new.tx_dropped, old.tx_dropped),
+    }
+}
+
+pub fn initial_net_dev_stat(filter: NetFilter) -> Result<NetDevIoStat> {
+    Ok(NetDevIoStat {
+        devs: read_net_stats(&filter)?,
+        filter,
+        last_time: Instant::now(),
+    })
+}
+
+pub fn collect_net_info(stat: &mut NetDevIoStat) -> Result<Vec<NetEntry>> {
+    let devs = read_net_stats(&stat.filter)?;
+    let now = Instant::now();
+    let secs = now.duration_since(stat.last_time).as_secs_f64();
+    let mut result = Vec::new();
+    for (name, new) in &devs {
+        if let Some(old) = stat.devs.get(name) {
+            result.push(get_net_entry(old, new, secs));
+        }
+    }
+    stat.devs = devs;
+    stat.last_time = now;
+    Ok(result)
+}
+
+pub fn do_net_statistic(samples: &[&[NetEntry]]) -> String {
+    let mut devs: BTreeMap<&str, Vec<&NetEntry>> = BTreeMap::new();
+    for s in samples {
+        for d in s.iter() {
+            devs.entry(&d.name).or_default().push(d);
+        }
+    }
+    if devs.is_empty() {
+        return "Network:\t\tno interface to report".to_string();
+    }
+    let mut ret = Vec::new();
+    for (name, entries) in devs {
+        let values = |f: fn(&NetEntry) -> f64| -> Vec<f64> { entries.iter().map(|d| f(d)).collect() };
+        ret.push(format_stat(&format!("{} rx MB/s:", name), &values(|d| d.rx_mbps),
+                             &[1.0, 10.0, 100.0, 1000.0], &["<1", "1-10", "10-100", "100-1000", ">=1000"]));
+        ret.push(format_stat(&format!("{} tx MB/s:", name), &values(|d| d.tx_mbps),
+                             &[1.0, 10.0, 100.0, 1000.0], &["<1", "1-10", "10-100", "100-1000", ">=1000"]));
+        ret.push(format_stat(&format!("{} rx packets/s:", name), &values(|d| d.rx_packets),
+                             &[100.0, 1000.0, 10000.0, 100000.0], &["<100", "100-1k", "1k-10k", "10k-100k", ">=100k"]));
+        ret.push(format_stat(&format!("{} tx packets/s:", name), &values(|d| d.tx_packets),
+                             &[100.0, 1000.0, 10000.0, 100000.0], &["<100", "100-1k", "1k-10k", "10k-100k", ">=100k"]));
+        ret.push(format_stat(&format!("{} errors/s:", name), &values(|d| d.errors),
+                             &[0.01, 1.0, 10.0], &["0", "<1", "1-10", ">=10"]));
+        ret.push(format_stat(&format!("{} drops/s:", name), &values(|d| d.dropped),
+                             &[0.01, 1.0, 10.0], &["0", "<1", "1-10", ">=10"]));
+    }
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_net_filter() {
+        let filter = NetFilter::new("", DEFAULT_NET_EXCLUDE);
+        assert!(filter.matches("eth0"));
+        assert!(!filter.matches("lo"));
+        assert!(!filter.matches("veth3f2a"));
+        let filter = NetFilter::new("en*, wl*", "");
+        assert!(filter.matches("enp3s0"));
+        assert!(!filter.matches("docker0"));
+    }
+
+    #[test]
+    fn test_read_sys_net_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = dir.path().join("eth0/statistics");
+        fs::create_dir_all(&stats).unwrap();
+        for name in &["rx_packets", "rx_errors", "rx_dropped", "tx_bytes", "tx_packets", "tx_errors", "tx_dropped"] {
+            fs::write(stats.join(name), "0\n").unwrap();
+        }
+        fs::write(dir.path().join("bonding_masters"), "\n").unwrap();
+        assert!(read_sys_net_stats(dir.path()).unwrap().is_empty());
+        fs::write(stats.join("rx_bytes"), "2097152\n").unwrap();
+        let old = NetDevStat { name: "eth0".to_string(), ..Default::default() };
+        let new = read_sys_net_stats(dir.path()).unwrap();
+        let entry = get_net_entry(&old, &new[0], 2.0);
+        assert_eq!(entry.name, "eth0");
+        assert_eq!(entry.rx_mbps, 1.0);
+        assert_eq!(entry.errors, 0.0);
+    }
+}
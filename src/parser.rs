@@ -0,0 +1,741 @@
+use std::collections::BTreeMap;
+use serde_json::Value;
+use crate::errors::*;
+
+// Well above the largest NR_CPUS, so that a corrupt cpu list can't allocate without bound
+const MAX_CPU_ID: usize = 65535;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct CpuStat {
+    pub total: u64,
+    pub idle: u64,
+}
+
+/// The interesting parts of /proc/stat.
+#[derive(PartialEq, Debug, Default)]
+pub struct ProcStat {
+    pub cpus: BTreeMap<usize, CpuStat>,
+    // Context switches, interrupts and forks since boot
+    pub ctxt: u64,
+    pub intr: u64,
+    pub processes: u64,
+    pub procs_running: u64,
+    pub procs_blocked: u64,
+}
+
+/// /proc/loadavg, e.g. "0.52 0.58 0.59 3/1234 56789".
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct LoadAvg {
+    pub load1: f64,
+    pub load5: f64,
+    pub load15: f64,
+    pub runnable: u64,
+    pub threads: u64,
+}
+
+/// Counters of one /proc/diskstats line. Sectors are always 512 bytes, ticks are in ms.
+#[derive(PartialEq, Debug, Clone)]
+pub struct DiskStat {
+    pub name: String,
+    pub reads: u64,
+    pub sectors_read: u64,
+    pub read_ticks: u64,
+    pub writes: u64,
+    pub sectors_written: u64,
+    pub write_ticks: u64,
+    pub io_ticks: u64,
+}
+
+/// Counters of one network interface.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct NetDevStat {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
+
+/// The interesting fields of /proc/<pid>/stat or /proc/<pid>/task/<tid>/stat.
+/// CPU times are in clock ticks.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct PidStat {
+    pub pid: usize,
+    pub comm: String,
+    pub ppid: usize,
+    pub utime: u64,
+    pub stime: u64,
+    pub num_threads: u64,
+    // Clock ticks after boot the process started, tells a reused pid apart
+    pub starttime: u64,
+    // CPU the task last ran on
+    pub processor: usize,
+}
+
+/// The interesting fields of /proc/<pid>/status, RSS is in kB.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct PidStatus {
+    pub rss: u64,
+    pub threads: u64,
+    pub voluntary_ctxt_switches: u64,
+    pub nonvoluntary_ctxt_switches: u64,
+}
+
+/// One device of a cgroup's io.stat.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct IoStat {
+    pub device: String,
+    pub rbytes: u64,
+    pub wbytes: u64,
+    pub rios: u64,
+    pub wios: u64,
+}
+
+/// One feature of "sensors -j". Values are in V, A, W, °C or RPM, and attr
+/// is the hwmon input the feature comes from, e.g. "temp1".
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct SensorsFeature {
+    // The whole chip key, e.g. "nvme-pci-0100", which tells chips of the same name apart
+    pub device: String,
+    pub chip: String,
+    pub label: String,
+    pub attr: String,
+    pub input: f64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub crit: Option<f64>,
+    pub alarm: bool,
+}
+
+/// One line of a PSI file. Averages are percentages, total is in microseconds.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct PressureLine {
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    pub total: u64,
+}
+
+/// A PSI file. "full" is missing for the CPU on kernels older than 5.13.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Pressure {
+    pub some: PressureLine,
+    pub full: Option<PressureLine>,
+}
+
+fn parse_error(file: &str, lineno: usize, line: &str, reason: &str) -> Error {
+    ErrorKind::ParseFailed(file.to_string(), lineno + 1, line.to_string(), reason.to_string()).into()
+}
+
+/// Parse a kernel cpu list like "0-3,8,10-11" into CPU ids.
+pub fn parse_cpu_list(file: &str, contents: &str) -> Result<Vec<usize>> {
+    let line = contents.trim();
+    let mut result = Vec::new();
+    for range in line.split(',').filter(|r| !r.is_empty()) {
+        let (first, last) = match range.find('-') {
+            Some(pos) => (&range[..pos], &range[(pos + 1)..]),
+            None => (range, range),
+        };
+        let (first, last) = match (first.parse::<usize>(), last.parse::<usize>()) {
+            (Ok(f), Ok(l)) if f <= l => (f, l),
+            _ => return Err(parse_error(file, 0, line, "invalid cpu range")),
+        };
+        if last > MAX_CPU_ID {
+            return Err(parse_error(file, 0, line, "cpu id out of range"));
+        }
+        result.extend(first..=last);
+    }
+    Ok(result)
+}
+
+/// Parse /proc/stat. Only the "cpuN" lines are used, the summary "cpu" line is skipped.
+pub fn parse_proc_stat(file: &str, contents: &str) -> Result<ProcStat> {
+    let mut result = ProcStat::default();
+    for (lineno, line) in contents.lines().enumerate() {
+        let mut fields = line.split_whitespace();
+        let name = match fields.next() {
+            Some(n) => n,
+            None => continue,
+        };
+        let counter = match name {
+            "ctxt" => Some(&mut result.ctxt),
+            // The first number is the total of all interrupts
+            "intr" => Some(&mut result.intr),
+            "processes" => Some(&mut result.processes),
+            "procs_running" => Some(&mut result.procs_running),
+            "procs_blocked" => Some(&mut result.procs_blocked),
+            _ => None,
+        };
+        if let Some(counter) = counter {
+            *counter = match fields.next().map(|f| f.parse::<u64>()) {
+                Some(Ok(v)) => v,
+                _ => return Err(parse_error(file, lineno, line, "invalid counter")),
+            };
+            continue;
+        }
+        let id = match name.strip_prefix("cpu") {
+            Some("") | None => continue,
+            Some(id) => id.parse::<usize>()
+                .map_err(|_| parse_error(file, lineno, line, "invalid cpu id"))?,
+        };
+        let mut total: u64 = 0;
+        let mut times = Vec::new();
+        for f in fields {
+            let t = f.parse::<u64>().map_err(|_| parse_error(file, lineno, line, "invalid cpu time"))?;
+            total = total.checked_add(t).ok_or_else(|| parse_error(file, lineno, line, "cpu time overflow"))?;
+            times.push(t);
+        }
+        // The 4th member is idle jiffies, kernel won't break userspace
+        let idle = match times.get(3) {
+            Some(i) => *i,
+            None => return Err(parse_error(file, lineno, line, "missing idle time")),
+        };
+        result.cpus.insert(id, CpuStat { total, idle });
+    }
+    Ok(result)
+}
+
+/// Parse /proc/<pid>/stat. The command name may contain spaces and parentheses.
+pub fn parse_pid_stat(file: &str, contents: &str) -> Result<PidStat> {
+    let line = contents.lines().next().unwrap_or("");
+    let (open, close) = match (line.find('('), line.rfind(')')) {
+        (Some(open), Some(close)) if open < close => (open, close),
+        _ => return Err(parse_error(file, 0, line, "missing command name")),
+    };
+    // Counted from the state, which is the 3rd field in proc(5)
+    let fields: Vec<&str> = line[(close + 1)..].split_whitespace().collect();
+    if fields.len() < 37 {
+        return Err(parse_error(file, 0, line, "too few fields"));
+    }
+    let invalid = |_| parse_error(file, 0, line, "invalid field");
+    Ok(PidStat {
+        pid: line[..open].trim().parse::<usize>().map_err(invalid)?,
+        comm: line[(open + 1)..close].to_string(),
+        ppid: fields[1].parse::<usize>().map_err(invalid)?,
+        utime: fields[11].parse::<u64>().map_err(invalid)?,
+        stime: fields[12].parse::<u64>().map_err(invalid)?,
+        num_threads: fields[17].parse::<u64>().map_err(invalid)?,
+        starttime: fields[19].parse::<u64>().map_err(invalid)?,
+        processor: fields[36].parse::<usize>().map_err(invalid)?,
+    })
+}
+
+/// Parse /proc/<pid>/status. Kernel threads have no VmRSS line.
+pub fn parse_pid_status(file: &str, contents: &str) -> Result<PidStatus> {
+    let mut result = PidStatus::default();
+    for (lineno, line) in contents.lines().enumerate() {
+        let (key, value) = match line.find(':') {
+            Some(pos) => (&line[..pos], &line[(pos + 1)..]),
+            None => continue,
+        };
+        let field = match key {
+            "VmRSS" => &mut result.rss,
+            "Threads" => &mut result.threads,
+            "voluntary_ctxt_switches" => &mut result.voluntary_ctxt_switches,
+            "nonvoluntary_ctxt_switches" => &mut result.nonvoluntary_ctxt_switches,
+            _ => continue,
+        };
+        *field = match value.split_whitespace().next().map(|v| v.parse::<u64>()) {
+            Some(Ok(v)) => v,
+            _ => return Err(parse_error(file, lineno, line, "invalid value")),
+        };
+    }
+    Ok(result)
+}
+
+/// Parse the output of "sensors -j", a tree of chip -> feature -> subfeature.
+pub fn parse_sensors_json(file: &str, contents: &str) -> Result<Vec<SensorsFeature>> {
+    let root: Value = serde_json::from_str(contents)
+        .map_err(|e| Error::from(ErrorKind::ParseFailed(file.to_string(), e.line(), String::new(), e.to_string())))?;
+    let chips = match root.as_object() {
+        Some(c) => c,
+        None => bail!("The output of {} is not a JSON object.", file),
+    };
+    let mut result = Vec::new();
+    for (chip_name, chip) in chips {
+        let features = match chip.as_object() {
+            Some(f) => f,
+            None => bail!("Chip {} in {} output is not a JSON object.", chip_name, file),
+        };
+        // e.g. "k10temp-pci-00c3", hwmon names never contain '-'
+        let device = chip_name;
+        let chip_name = chip_name.split('-').next().unwrap_or("");
+        // "Adapter" is a string, the features are objects
+        for (label, feature) in features.iter().filter_map(|(l, f)| f.as_object().map(|f| (l, f))) {
+            let value = |key: &str| feature.get(key).and_then(|v| v.as_f64());
+            // Some power sensors only have an average
+            let find = |suffix: &str| feature.iter()
+                .find_map(|(k, v)| k.strip_suffix(suffix).and_then(|a| v.as_f64().map(|v| (a, v))));
+            let (attr, input) = match find("_input").or_else(|| find("_average")) {
+                Some(i) => i,
+                None => continue,
+            };
+            // A limit of 0 means it is not set
+            let limit = |suffix: &str| value(&format!("{}{}", attr, suffix)).filter(|v| *v != 0.0);
+            result.push(SensorsFeature {
+                device: device.to_string(),
+                chip: chip_name.to_string(),
+                label: label.to_string(),
+                attr: attr.to_string(),
+                input,
+                min: limit("_min"),
+                max: limit("_max"),
+                crit: limit("_crit"),
+                alarm: feature.iter().any(|(k, v)| k.ends_with("_alarm") && v.as_f64().is_some_and(|a| a != 0.0)),
+            });
+        }
+    }
+    Ok(result)
+}
+
+/// Parse a cgroup v2 io.stat, e.g. "8:0 rbytes=90112 wbytes=0 rios=5 wios=0 dbytes=0 dios=0".
+pub fn parse_io_stat(file: &str, contents: &str) -> Result<Vec<IoStat>> {
+    let mut result = Vec::new();
+    for (lineno, line) in contents.lines().enumerate() {
+        let mut fields = line.split_whitespace();
+        let mut io = match fields.next() {
+            Some(d) => IoStat { device: d.to_string(), ..Default::default() },
+            None => continue,
+        };
+        for f in fields {
+            let (key, value) = match f.find('=') {
+                Some(pos) => (&f[..pos], &f[(pos + 1)..]),
+                None => return Err(parse_error(file, lineno, line, "missing '='")),
+            };
+            let counter = match key {
+                "rbytes" => &mut io.rbytes,
+                "wbytes" => &mut io.wbytes,
+                "rios" => &mut io.rios,
+                "wios" => &mut io.wios,
+                _ => continue,
+            };
+            *counter = value.parse::<u64>().map_err(|_| parse_error(file, lineno, line, "invalid counter"))?;
+        }
+        result.push(io);
+    }
+    Ok(result)
+}
+
+/// Parse /proc/loadavg.
+pub fn parse_loadavg(file: &str, contents: &str) -> Result<LoadAvg> {
+    let line = contents.lines().next().unwrap_or("");
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 4 {
+        return Err(parse_error(file, 0, line, "too few fields"));
+    }
+    let load = |f: &str| f.parse::<f64>().map_err(|_| parse_error(file, 0, line, "invalid load average"));
+    let (runnable, threads) = match fields[3].find('/') {
+        Some(pos) => (&fields[3][..pos], &fields[3][(pos + 1)..]),
+        None => return Err(parse_error(file, 0, line, "missing '/'")),
+    };
+    let count = |f: &str| f.parse::<u64>().map_err(|_| parse_error(file, 0, line, "invalid task count"));
+    Ok(LoadAvg {
+        load1: load(fields[0])?,
+        load5: load(fields[1])?,
+        load15: load(fields[2])?,
+        runnable: count(runnable)?,
+        threads: count(threads)?,
+    })
+}
+
+/// Parse /proc/cpuinfo into the "cpu MHz" of every "processor".
+pub fn parse_cpuinfo(file: &str, contents: &str) -> Result<BTreeMap<usize, f64>> {
+    let mut result = BTreeMap::new();
+    let mut id = None;
+    for (lineno, l) in contents.lines().enumerate() {
+        let line = l.trim();
+        let is_processor = line.starts_with("processor");
+        if !is_processor && !line.starts_with("cpu MHz") {
+            continue;
+        }
+        let value = match line.find(':') {
+            Some(pos) => line[(pos + 1)..].trim(),
+            None => return Err(parse_error(file, lineno, l, "missing ':'")),
+        };
+        if is_processor {
+            id = Some(value.parse::<usize>().map_err(|_| parse_error(file, lineno, l, "invalid processor id"))?);
+            continue;
+        }
+        let freq = match value.parse::<f64>() {
+            Ok(f) if f.is_finite() && f >= 0.0 => f,
+            _ => return Err(parse_error(file, lineno, l, "invalid frequency")),
+        };
+        match id {
+            Some(i) => { result.insert(i, freq); },
+            None => return Err(parse_error(file, lineno, l, "frequency before any processor")),
+        }
+    }
+    Ok(result)
+}
+
+/// Parse "key value" files like /proc/vmstat, or "Key: value kB" ones like /proc/meminfo.
+/// Units are dropped, lines which don't end with a number are skipped.
+pub fn parse_key_values(file: &str, contents: &str) -> Result<BTreeMap<String, u64>> {
+    let mut result = BTreeMap::new();
+    for (lineno, line) in contents.lines().enumerate() {
+        let mut fields = line.split_whitespace();
+        let key = match fields.next() {
+            Some(k) => k.trim_end_matches(':'),
+            None => continue,
+        };
+        let value = match fields.next() {
+            Some(v) => v,
+            None => return Err(parse_error(file, lineno, line, "missing value")),
+        };
+        if let Ok(v) = value.parse::<u64>() {
+            result.insert(key.to_string(), v);
+        }
+    }
+    Ok(result)
+}
+
+/// Parse /proc/diskstats.
+pub fn parse_diskstats(file: &str, contents: &str) -> Result<Vec<DiskStat>> {
+    let mut result = Vec::new();
+    for (lineno, line) in contents.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        // major, minor, name and at least 10 counters
+        if fields.len() < 13 {
+            return Err(parse_error(file, lineno, line, "missing counters"));
+        }
+        let mut v = Vec::new();
+        for f in &fields[3..13] {
+            v.push(f.parse::<u64>().map_err(|_| parse_error(file, lineno, line, "invalid counter"))?);
+        }
+        result.push(DiskStat {
+            name: fields[2].to_string(),
+            reads: v[0],
+            sectors_read: v[2],
+            read_ticks: v[3],
+            writes: v[4],
+            sectors_written: v[6],
+            write_ticks: v[7],
+            io_ticks: v[9],
+        });
+    }
+    Ok(result)
+}
+
+/// Parse /proc/net/dev. The two header lines have no ':' and are skipped.
+pub fn parse_net_dev(file: &str, contents: &str) -> Result<Vec<NetDevStat>> {
+    let mut result = Vec::new();
+    for (lineno, line) in contents.lines().enumerate() {
+        // Big counters are glued to the colon, e.g. "eth0:1234567890"
+        let pos = match line.find(':') {
+            Some(p) => p,
+            None => continue,
+        };
+        let mut v = Vec::new();
+        for f in line[(pos + 1)..].split_whitespace() {
+            v.push(f.parse::<u64>().map_err(|_| parse_error(file, lineno, line, "invalid counter"))?);
+        }
+        if v.len() < 12 {
+            return Err(parse_error(file, lineno, line, "missing counters"));
+        }
+        result.push(NetDevStat {
+            name: line[..pos].trim().to_string(),
+            rx_bytes: v[0],
+            rx_packets: v[1],
+            rx_errors: v[2],
+            rx_dropped: v[3],
+            tx_bytes: v[8],
+            tx_packets: v[9],
+            tx_errors: v[10],
+            tx_dropped: v[11],
+        });
+    }
+    Ok(result)
+}
+
+/// Parse a PSI file like /proc/pressure/cpu or a cgroup's cpu.pressure.
+pub fn parse_pressure(file: &str, contents: &str) -> Result<Pressure> {
+    let mut some = None;
+    let mut full = None;
+    for (lineno, line) in contents.lines().enumerate() {
+        let mut fields = line.split_whitespace();
+        let kind = match fields.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let mut p = PressureLine::default();
+        for f in fields {
+            let (key, value) = match f.find('=') {
+                Some(pos) => (&f[..pos], &f[(pos + 1)..]),
+                None => return Err(parse_error(file, lineno, line, "missing '='")),
+            };
+            let invalid = |_| parse_error(file, lineno, line, "invalid value");
+            match key {
+                "avg10" => p.avg10 = value.parse::<f64>().map_err(invalid)?,
+                "avg60" => p.avg60 = value.parse::<f64>().map_err(invalid)?,
+                "avg300" => p.avg300 = value.parse::<f64>().map_err(invalid)?,
+                "total" => p.total = value.parse::<u64>().map_err(|_| parse_error(file, lineno, line, "invalid total"))?,
+                _ => (),
+            }
+        }
+        match kind {
+            "some" => some = Some(p),
+            "full" => full = Some(p),
+            _ => return Err(parse_error(file, lineno, line, "unknown pressure kind")),
+        }
+    }
+    match some {
+        Some(some) => Ok(Pressure { some, full }),
+        None => bail!("No \"some\" line is found in {}.", file),
+    }
+}
+
+/// Parse the temperature line of "nvme smart-log", e.g. "temperature : 45 C"
+/// or "temperature : 45°C (318 Kelvin)".
+pub fn parse_nvme_temperature(file: &str, contents: &str) -> Result<f64> {
+    for (lineno, l) in contents.lines().enumerate() {
+        let line = l.trim();
+        if !line.starts_with("temperature") {
+            continue;
+        }
+        let value = match line.find(':') {
+            Some(pos) => line[(pos + 1)..].trim(),
+            None => return Err(parse_error(file, lineno, l, "missing ':'")),
+        };
+        let temp: String = value.chars().take_while(|c| c.is_ascii_digit() || "+-.".contains(*c)).collect();
+        return temp.parse::<f64>().map_err(|_| parse_error(file, lineno, l, "invalid temperature"));
+    }
+    bail!("No temperature line is found in {} output.", file);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const PROC_STAT: &str = "cpu  2255 34 2290 22625563 6290 127 456 0 0 0\n\
+                             cpu0 1132 34 1441 11311718 3675 127 438 0 0 0\n\
+                             cpu1 1123 0 849 11313845 2614 0 18 0 0 0\n\
+                             intr 114930548 113199788 3 0 5 263 0 4\n\
+                             ctxt 1990473\n\
+                             btime 1062191376\n\
+                             processes 2915\n\
+                             procs_running 1\n\
+                             procs_blocked 0\n";
+    const CPUINFO: &str = "processor\t: 0\nvendor_id\t: AuthenticAMD\ncpu MHz\t\t: 3600.000\n\n\
+                           processor\t: 1\nvendor_id\t: AuthenticAMD\ncpu MHz\t\t: 2200.137\n";
+    const NVME: &str = "Smart Log for NVME device:nvme0n1 namespace-id:ffffffff\n\
+                        critical_warning\t\t\t: 0\n\
+                        temperature\t\t\t\t: 45 C\n\
+                        available_spare\t\t\t\t: 100%\n";
+
+    #[test]
+    fn test_parse_samples() {
+        let stat = parse_proc_stat("/proc/stat", PROC_STAT).unwrap();
+        assert_eq!(stat.cpus.len(), 2);
+        assert_eq!(stat.cpus[&1], CpuStat { total: 11318449, idle: 11313845 });
+        assert_eq!((stat.ctxt, stat.intr, stat.processes), (1990473, 114930548, 2915));
+        assert_eq!((stat.procs_running, stat.procs_blocked), (1, 0));
+        let load = parse_loadavg("/proc/loadavg", "0.52 0.58 0.59 3/1234 56789\n").unwrap();
+        assert_eq!(load, LoadAvg { load1: 0.52, load5: 0.58, load15: 0.59, runnable: 3, threads: 1234 });
+
+        let freqs = parse_cpuinfo("/proc/cpuinfo", CPUINFO).unwrap();
+        assert_eq!(freqs.get(&1), Some(&2200.137));
+        assert_eq!(parse_nvme_temperature("nvme smart-log", NVME).unwrap(), 45.0);
+        assert_eq!(parse_nvme_temperature("nvme smart-log", "temperature\t\t: -5.5°C (267 Kelvin)\n").unwrap(), -5.5);
+        assert_eq!(parse_cpu_list("online", "0-3,8,10-11\n").unwrap(), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpu_list("online", "\n").unwrap(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_parse_key_values() {
+        let meminfo = parse_key_values("/proc/meminfo", "MemTotal:        6158152 kB\nDirty:     84 kB\n").unwrap();
+        assert_eq!(meminfo["MemTotal"], 6158152);
+        assert_eq!(meminfo["Dirty"], 84);
+        let vmstat = parse_key_values("/proc/vmstat", "pgfault 4948459\npgmajfault 598\n").unwrap();
+        assert_eq!(vmstat["pgmajfault"], 598);
+        assert!(parse_key_values("/proc/vmstat", "pgfault\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_diskstats() {
+        let stats = parse_diskstats("/proc/diskstats",
+                                    " 259       0 nvme0n1 1000 5 80000 300 500 2 40000 200 0 450 500 0 0 0 0\n").unwrap();
+        assert_eq!(stats[0].name, "nvme0n1");
+        assert_eq!(stats[0].sectors_written, 40000);
+        assert_eq!(stats[0].io_ticks, 450);
+        assert!(parse_diskstats("/proc/diskstats", "8 0 sda 1 2 3\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_net_dev() {
+        let dev = "Inter-|   Receive                                                |  Transmit\n                    face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed\n                       lo: 16433864    1802    0    0    0     0          0         0 16433864    1802    0    0    0     0       0          0\n                     eth0:4294967296 104    1    2    0     0          0         0    10808     110    3    4    0     0       0          0\n";
+        let stats = parse_net_dev("/proc/net/dev", dev).unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[1], NetDevStat {
+            name: "eth0".to_string(),
+            rx_bytes: 4294967296, rx_packets: 104, rx_errors: 1, rx_dropped: 2,
+            tx_bytes: 10808, tx_packets: 110, tx_errors: 3, tx_dropped: 4,
+        });
+    }
+
+    #[test]
+    fn test_parse_pid_files() {
+        let stat = "4242 (my (odd) app) S 1 4242 4242 0 -1 4194560 1250 0 0 0 350 120 0 0 20 0 6 0 \
+                    1000 123456789 2048 18446744073709551615 1 1 0 0 0 0 0 4096 0 0 0 0 17 3 0 0 0 0 0\n";
+        assert_eq!(parse_pid_stat("/proc/4242/stat", stat).unwrap(), PidStat {
+            pid: 4242, comm: "my (odd) app".to_string(), ppid: 1, utime: 350, stime: 120, num_threads: 6,
+            starttime: 1000, processor: 3,
+        });
+        assert!(parse_pid_stat("/proc/4242/stat", "4242 (app) S 1 2 3\n").is_err());
+        let status = "Name:\tapp\nGroups:\t\nVmRSS:\t  20480 kB\nThreads:\t6\n\
+                      voluntary_ctxt_switches:\t150\nnonvoluntary_ctxt_switches:\t7\n";
+        assert_eq!(parse_pid_status("/proc/4242/status", status).unwrap(), PidStatus {
+            rss: 20480, threads: 6, voluntary_ctxt_switches: 150, nonvoluntary_ctxt_switches: 7,
+        });
+        assert!(parse_pid_status("/proc/4242/status", "Threads:\tmany\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_sensors_json() {
+        let b450 = parse_sensors_json("sensors -j", include_str!("../testdata/sensors-asus-b450-i.json")).unwrap();
+        assert_eq!(b450.len(), 10);
+        let cpu = b450.iter().find(|f| f.label == "CPU Temperature").unwrap();
+        assert_eq!((cpu.chip.as_str(), cpu.attr.as_str(), cpu.input), ("asus_wmi_sensors", "temp1", 45.0));
+        let nvme = b450.iter().find(|f| f.chip == "nvme").unwrap();
+        assert_eq!(*nvme, SensorsFeature {
+            device: "nvme-pci-0100".to_string(), chip: "nvme".to_string(), label: "Composite".to_string(),
+            attr: "temp1".to_string(), input: 38.85,
+            min: Some(-273.15), max: Some(81.85), crit: Some(84.85), alarm: false,
+        });
+
+        let nct = parse_sensors_json("sensors -j", include_str!("../testdata/sensors-nct6798.json")).unwrap();
+        let attrs: Vec<(&str, &str)> = nct.iter().map(|f| (f.chip.as_str(), f.attr.as_str())).collect();
+        assert_eq!(attrs, vec![("amdgpu", "power1"), ("amdgpu", "in0"), ("nct6798", "temp1"), ("nct6798", "fan2"), ("nct6798", "in0")]);
+        let fan = nct.iter().find(|f| f.attr == "fan2").unwrap();
+        assert_eq!((fan.input, fan.min, fan.alarm), (547.0, Some(600.0), true));
+        let vddgfx = nct.iter().find(|f| f.label == "vddgfx").unwrap();
+        assert_eq!((vddgfx.input, vddgfx.max), (0.775, None));
+        assert_eq!(nct.iter().find(|f| f.label == "PPT").unwrap().input, 9.0);
+        assert!(!nct.iter().any(|f| f.label == "intrusion0"));
+
+        let rpi = parse_sensors_json("sensors -j", include_str!("../testdata/sensors-rpi4.json")).unwrap();
+        assert_eq!(rpi.len(), 1);
+        assert_eq!(rpi[0].input, -5.312);
+
+        assert!(parse_sensors_json("sensors -j", "{\"chip\": 1}").is_err());
+        assert!(parse_sensors_json("sensors -j", "[1]").is_err());
+        assert!(parse_sensors_json("sensors -j", "{\"chip\": {").is_err());
+    }
+
+    #[test]
+    fn test_parse_io_stat() {
+        let stats = parse_io_stat("io.stat", "8:0 rbytes=90112 wbytes=4096 rios=5 wios=1 dbytes=0 dios=0\n\
+                                              259:0 rbytes=1 wbytes=2 rios=3 wios=4\n").unwrap();
+        assert_eq!(stats[0], IoStat { device: "8:0".to_string(), rbytes: 90112, wbytes: 4096, rios: 5, wios: 1 });
+        assert_eq!(stats.len(), 2);
+        assert!(parse_io_stat("io.stat", "8:0 rbytes\n").is_err());
+        assert!(parse_io_stat("io.stat", "8:0 rios=-1\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_pressure() {
+        let p = parse_pressure("/proc/pressure/cpu", "some avg10=4.07 avg60=2.77 avg300=2.68 total=25712407\n\
+                                                      full avg10=0.00 avg60=0.00 avg300=0.00 total=0\n").unwrap();
+        assert_eq!(p.some.avg10, 4.07);
+        assert_eq!(p.some.total, 25712407);
+        assert_eq!(p.full.unwrap().total, 0);
+        let p = parse_pressure("/proc/pressure/cpu", "some avg10=0.00 avg60=0.00 avg300=0.00 total=1\n").unwrap();
+        assert_eq!(p.full, None);
+        assert!(parse_pressure("/proc/pressure/io", "some avg10=x total=1\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let e = parse_proc_stat("/proc/stat", "cpu  1 2 3 4\ncpu0 1 2 x 4\n").unwrap_err();
+        assert_eq!(e.to_string(), "Failed to parse /proc/stat line 2 'cpu0 1 2 x 4': invalid cpu time");
+        assert!(parse_proc_stat("/proc/stat", "ctxt\n").is_err());
+        assert!(parse_loadavg("/proc/loadavg", "0.52 0.58 0.59 3\n").is_err());
+        let e = parse_proc_stat("/proc/stat", "cpu0 1 2 3\n").unwrap_err();
+        assert_eq!(e.to_string(), "Failed to parse /proc/stat line 1 'cpu0 1 2 3': missing idle time");
+        let e = parse_proc_stat("/proc/stat", "cpu0 18446744073709551615 1 2 3\n").unwrap_err();
+        assert_eq!(e.to_string(), "Failed to parse /proc/stat line 1 'cpu0 18446744073709551615 1 2 3': cpu time overflow");
+        assert!(parse_cpuinfo("/proc/cpuinfo", "cpu MHz : 3600\n").is_err());
+        assert!(parse_nvme_temperature("nvme smart-log", "temperature : -- C\n").is_err());
+        assert!(parse_cpu_list("online", "0-x").is_err());
+        assert!(parse_cpu_list("online", "3-1").is_err());
+        let e = parse_cpu_list("online", "0-18446744073709551615\n").unwrap_err();
+        assert_eq!(e.to_string(), "Failed to parse online line 1 '0-18446744073709551615': cpu id out of range");
+    }
+
+    // Flip, drop or insert a byte at every given position of a sample
+    fn mutate(sample: &str, edits: &[(usize, u8, u8)]) -> String {
+        let mut bytes = sample.as_bytes().to_vec();
+        for &(pos, op, b) in edits {
+            let pos = pos % (bytes.len() + 1);
+            match op % 3 {
+                0 if pos < bytes.len() => bytes[pos] = b,
+                1 if pos < bytes.len() => { bytes.remove(pos); },
+                _ => bytes.insert(pos, b),
+            }
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_arbitrary_input_never_panics(s in "\\PC*") {
+            let _ = parse_proc_stat("/proc/stat", &s);
+            let _ = parse_cpuinfo("/proc/cpuinfo", &s);
+            let _ = parse_nvme_temperature("nvme smart-log", &s);
+            let _ = parse_key_values("/proc/meminfo", &s);
+            let _ = parse_diskstats("/proc/diskstats", &s);
+            let _ = parse_net_dev("/proc/net/dev", &s);
+            let _ = parse_pressure("/proc/pressure/cpu", &s);
+            let _ = parse_loadavg("/proc/loadavg", &s);
+            let _ = parse_pid_stat("/proc/1/stat", &s);
+            let _ = parse_pid_status("/proc/1/status", &s);
+            let _ = parse_io_stat("io.stat", &s);
+            let _ = parse_sensors_json("sensors -j", &s);
+            let _ = parse_cpu_list("online", &s);
+        }
+
+        #[test]
+        fn prop_stat_like_input_never_panics(s in "(cpu[0-9]{0,3}( [0-9]{0,21}){0,12}\n){0,8}") {
+            let _ = parse_proc_stat("/proc/stat", &s);
+        }
+
+        #[test]
+        fn prop_proc_stat_round_trips(cpus in proptest::collection::btree_map(0usize..4096,
+                                          proptest::collection::vec(0u64..1 << 40, 4..11), 0..8)) {
+            let contents: String = cpus.iter()
+                .map(|(id, times)| format!("cpu{} {}\n", id, times.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(" ")))
+                .collect();
+            let stat = parse_proc_stat("/proc/stat", &contents).unwrap();
+            prop_assert_eq!(stat.cpus.len(), cpus.len());
+            for (id, times) in &cpus {
+                prop_assert_eq!(&stat.cpus[id], &CpuStat { total: times.iter().sum(), idle: times[3] });
+            }
+        }
+
+        #[test]
+        fn prop_cpu_list_round_trips(ranges in proptest::collection::vec((0usize..=MAX_CPU_ID, 0usize..64), 0..8)) {
+            let list: Vec<String> = ranges.iter()
+                .map(|(f, n)| if *n == 0 { f.to_string() } else { format!("{}-{}", f, f + n) })
+                .collect();
+            let expected: Vec<usize> = ranges.iter().flat_map(|(f, n)| *f..=f + n).collect();
+            match parse_cpu_list("online", &list.join(",")) {
+                Ok(cpus) => prop_assert_eq!(cpus, expected),
+                Err(_) => prop_assert!(ranges.iter().any(|(f, n)| f + n > MAX_CPU_ID)),
+            }
+        }
+
+        #[test]
+        fn prop_mutated_samples_never_panic(edits in proptest::collection::vec((any::<usize>(), any::<u8>(), any::<u8>()), 1..16)) {
+            let _ = parse_proc_stat("/proc/stat", &mutate(PROC_STAT, &edits));
+            let _ = parse_cpuinfo("/proc/cpuinfo", &mutate(CPUINFO, &edits));
+            let _ = parse_nvme_temperature("nvme smart-log", &mutate(NVME, &edits));
+        }
+    }
+}
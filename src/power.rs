@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::errors::*;
+use crate::util::*;
+
+// The AMD RAPL driver registers its zones under the same name
+const POWERCAP_DIR: &str = "/sys/class/powercap";
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum RaplDomain {
+    Package,
+    Core,
+    Dram,
+}
+
+struct RaplZone {
+    domain: RaplDomain,
+    path: PathBuf,
+    max_range: u64,
+    last_energy: u64,
+}
+
+pub struct RaplStat {
+    zones: Vec<RaplZone>,
+    last_time: Instant,
+}
+
+/// Energy consumed during one sample interval, in joules.
+#[derive(PartialEq, Debug)]
+pub struct PowerEntry {
+    pub secs: f64,
+    pub package: f64,
+    pub core: f64,
+    pub dram: f64,
+}
+
+impl PowerEntry {
+    pub fn package_watts(&self) -> f64 {
+        self.package / self.secs
+    }
+
+    pub fn core_watts(&self) -> f64 {
+        self.core / self.secs
+    }
+
+    pub fn dram_watts(&self) -> f64 {
+        self.dram / self.secs
+    }
+}
+
+fn energy_delta(old: u64, new: u64, max_range: u64) -> u64 {
+    if new >= old {
+        new - old
+    } else {
+        // The counter wrapped around at max_energy_range_uj, which a bogus
+        // range doesn't make underflow
+        max_range.saturating_sub(old) + new
+    }
+}
+
+fn read_zone(path: PathBuf) -> Result<Option<RaplZone>> {
+    let name = fs::read_to_string(path.join("name"))?;
+    let domain = match name.trim() {
+        n if n.starts_with("package") => RaplDomain::Package,
+        "core" => RaplDomain::Core,
+        "dram" => RaplDomain::Dram,
+        _ => return Ok(None),
+    };
+    let max_range = read_sysfs_u64(&path.join("max_energy_range_uj"))?;
+    let last_energy = read_sysfs_u64(&path.join("energy_uj"))?;
+    Ok(Some(RaplZone { domain, path, max_range, last_energy }))
+}
+
+fn initial_rapl_stat_from(root: &Path) -> Result<RaplStat> {
+    let mut zones = Vec::new();
+    let entries = match fs::read_dir(root) {
+        Ok(o) => o,
+        Err(_) => return Ok(RaplStat { zones, last_time: Instant::now() }),
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let dir_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        // intel-rapl-mmio zones duplicate the MSR ones
+        if !dir_name.starts_with("intel-rapl:") {
+            continue;
+        }
+        // E.g. energy_uj is only readable by root since the PLATYPUS mitigation
+        match read_zone(path) {
+            Ok(Some(z)) => zones.push(z),
+            Ok(None) => (),
+            Err(e) => warn!("Skip RAPL zone {}: {}", dir_name, e),
+        }
+    }
+    Ok(RaplStat { zones, last_time: Instant::now() })
+}
+
+fn collect_power_info_from(stat: &mut RaplStat, now: Instant) -> Result<Option<PowerEntry>> {
+    let mut result = PowerEntry {
+        secs: now.duration_since(stat.last_time).as_secs_f64(),
+        package: 0.0,
+        core: 0.0,
+        dram: 0.0,
+    };
+    stat.zones.retain_mut(|zone| {
+        let energy = match read_sysfs_u64(&zone.path.join("energy_uj")) {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("Drop RAPL zone {}: {}", zone.path.display(), e);
+                return false;
+            },
+        };
+        let joules = energy_delta(zone.last_energy, energy, zone.max_range) as f64 / 1_000_000.0;
+        match zone.domain {
+            RaplDomain::Package => result.package += joules,
+            RaplDomain::Core => result.core += joules,
+            RaplDomain::Dram => result.dram += joules,
+        }
+        zone.last_energy = energy;
+        true
+    });
+    if stat.zones.is_empty() {
+        return Ok(None);
+    }
+    stat.last_time = now;
+    Ok(Some(result))
+}
+
+/// Find the RAPL zones and record their current energy counters.
+pub fn initial_rapl_stat() -> Result<RaplStat> {
+    initial_rapl_stat_from(Path::new(POWERCAP_DIR))
+}
+
+/// Get the energy consumed since the last call, None if RAPL is unavailable.
+pub fn collect_power_info(stat: &mut RaplStat) -> Result<Option<PowerEntry>> {
+    collect_power_info_from(stat, Instant::now())
+}
+
+fn power_line(name: &str, watts: &[f64], joules: f64) -> String {
+    let len = watts.len() as f64;
+    let sum: f64 = watts.iter().sum();
+    let min = watts.iter().cloned().fold(f64::MAX, f64::min);
+    let max = watts.iter().cloned().fold(0.0, f64::max);
+    format!("{}\tavg: {:.2}W | min: {:.2}W | max: {:.2}W | energy: {:.2}J ({:.4}Wh)",
+            name, sum / len, min, max, joules, joules / 3600.0)
+}
+
+/// `avg_freq` is the average frequency of all CPUs over the run, in MHz.
+pub fn do_power_statistic(power: &[&PowerEntry], avg_freq: f64) -> String {
+    if power.is_empty() {
+        return "Power:\t\t\tRAPL power info is unavailable".to_string();
+    }
+    let package: Vec<f64> = power.iter().map(|p| p.package_watts()).collect();
+    let core: Vec<f64> = power.iter().map(|p| p.core_watts()).collect();
+    let dram: Vec<f64> = power.iter().map(|p| p.dram_watts()).collect();
+    let secs: f64 = power.iter().map(|p| p.secs).sum();
+    let package_joules: f64 = power.iter().map(|p| p.package).sum();
+    let core_joules: f64 = power.iter().map(|p| p.core).sum();
+    let dram_joules: f64 = power.iter().map(|p| p.dram).sum();
+
+    let mut ret = power_line("Package power:", &package, package_joules);
+    if core_joules > 0.0 {
+        ret = format!("{}\n{}", ret, power_line("Core power:\t", &core, core_joules));
+    }
+    if dram_joules > 0.0 {
+        ret = format!("{}\n{}", ret, power_line("DRAM power:\t", &dram, dram_joules));
+    }
+    let avg_package = package_joules / secs;
+    if avg_package > 0.0 {
+        ret = format!("{}\nEfficiency:\t\tavg MHz per package watt: {:.2}", ret, avg_freq / avg_package);
+        if core_joules > 0.0 {
+            ret = format!("{} | avg MHz per core watt: {:.2}", ret, avg_freq / (core_joules / secs));
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn write_zone(root: &Path, dir: &str, name: &str, energy: u64) {
+        write_attrs(&root.join(dir), &[("name", name), ("max_energy_range_uj", "1000000000"),
+                                       ("energy_uj", &energy.to_string())]);
+    }
+
+    #[test]
+    fn test_collect_power_info() {
+        let dir = tempfile::tempdir().unwrap();
+        write_zone(dir.path(), "intel-rapl:0", "package-0", 999_000_000);
+        write_zone(dir.path(), "intel-rapl:0:0", "core", 1_000_000);
+        write_zone(dir.path(), "intel-rapl-mmio:0", "package-0", 0);
+
+        let mut stat = initial_rapl_stat_from(dir.path()).unwrap();
+        assert_eq!(stat.zones.len(), 2);
+        // The package counter wraps around
+        write_zone(dir.path(), "intel-rapl:0", "package-0", 29_000_000);
+        write_zone(dir.path(), "intel-rapl:0:0", "core", 21_000_000);
+        let now = stat.last_time + Duration::from_secs(2);
+        let power = collect_power_info_from(&mut stat, now).unwrap().unwrap();
+        assert_eq!(power.package_watts(), 15.0);
+        assert_eq!(power.core_watts(), 10.0);
+        assert_eq!(power.dram, 0.0);
+        assert_eq!(energy_delta(5, 2, 1), 2);
+
+        // A zone which can't be read is skipped, or dropped once it fails
+        write_zone(dir.path(), "intel-rapl:1", "dram", 0);
+        fs::remove_file(dir.path().join("intel-rapl:1/energy_uj")).unwrap();
+        let mut stat = initial_rapl_stat_from(dir.path()).unwrap();
+        assert_eq!(stat.zones.len(), 2);
+        fs::remove_file(dir.path().join("intel-rapl:0:0/energy_uj")).unwrap();
+        let now = stat.last_time + Duration::from_secs(2);
+        assert_eq!(collect_power_info_from(&mut stat, now).unwrap().unwrap().core, 0.0);
+        assert_eq!(stat.zones.len(), 1);
+
+        let empty = tempfile::tempdir().unwrap();
+        let mut stat = initial_rapl_stat_from(&empty.path().join("none")).unwrap();
+        assert_eq!(collect_power_info(&mut stat).unwrap(), None);
+    }
+}
@@ -0,0 +1,181 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::errors::*;
+use crate::parser::*;
+use crate::statistic::*;
+
+const PRESSURE_DIR: &str = "/proc/pressure";
+const RESOURCES: [(&str, &str); 3] = [("cpu", "CPU"), ("memory", "Memory"), ("io", "IO")];
+
+struct PressureSource {
+    name: String,
+    path: PathBuf,
+    some_total: u64,
+    full_total: Option<u64>,
+}
+
+pub struct PressureStat {
+    sources: Vec<PressureSource>,
+    last_time: Instant,
+}
+
+/// All values are percentages. The stall time is the share of the interval
+/// in which some (or all) tasks were stalled on the resource.
+#[derive(PartialEq, Debug)]
+pub struct PressureEntry {
+    pub name: String,
+    pub some_avg10: f64,
+    pub some_avg60: f64,
+    pub some_avg300: f64,
+    pub some_stall: f64,
+    pub full_avg10: Option<f64>,
+    pub full_avg60: Option<f64>,
+    pub full_avg300: Option<f64>,
+    pub full_stall: Option<f64>,
+}
+
+fn read_pressure(path: &Path) -> Result<Pressure> {
+    parse_pressure(&path.to_string_lossy(), &fs::read_to_string(path)?)
+}
+
+fn add_source(sources: &mut Vec<PressureSource>, name: String, path: PathBuf) {
+    // PSI may be compiled out, disabled with "psi=0", or disabled per cgroup
+    match read_pressure(&path) {
+        Ok(p) => sources.push(PressureSource {
+            name,
+            path,
+            some_total: p.some.total,
+            full_total: p.full.map(|f| f.total),
+        }),
+        Err(e) => debug!("Skip pressure file {}: {}", path.display(), e),
+    }
+}
+
+fn initial_pressure_stat_from(dir: &Path, cgroup: Option<&Path>) -> PressureStat {
+    let mut sources = Vec::new();
+    for (file, name) in RESOURCES.iter() {
+        add_source(&mut sources, name.to_string(), dir.join(file));
+    }
+    if let Some(cgroup) = cgroup {
+        for (file, name) in RESOURCES.iter() {
+            add_source(&mut sources, format!("cgroup {}", name), cgroup.join(format!("{}.pressure", file)));
+        }
+    }
+    PressureStat { sources, last_time: Instant::now() }
+}
+
+fn collect_pressure_info_from(stat: &mut PressureStat, now: Instant) -> Result<Vec<PressureEntry>> {
+    let secs = now.duration_since(stat.last_time).as_secs_f64();
+    // "total" is in microseconds
+    let stall = |new: u64, old: u64| (new.saturating_sub(old) as f64 / 10_000.0 / secs).min(100.0);
+    let mut result = Vec::new();
+    // A cgroup's files go away with it, e.g. when its container stops
+    stat.sources.retain_mut(|s| {
+        let p = match read_pressure(&s.path) {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("Drop pressure file {}: {}", s.path.display(), e);
+                return false;
+            },
+        };
+        let full_total = p.full.as_ref().map(|f| f.total);
+        result.push(PressureEntry {
+            name: s.name.clone(),
+            some_avg10: p.some.avg10,
+            some_avg60: p.some.avg60,
+            some_avg300: p.some.avg300,
+            some_stall: stall(p.some.total, s.some_total),
+            full_avg10: p.full.as_ref().map(|f| f.avg10),
+            full_avg60: p.full.as_ref().map(|f| f.avg60),
+            full_avg300: p.full.as_ref().map(|f| f.avg300),
+            full_stall: full_total.map(|t| stall(t, s.full_total.unwrap_or(t))),
+        });
+        s.some_total = p.some.total;
+        s.full_total = full_total;
+        true
+    });
+    stat.last_time = now;
+    Ok(result)
+}
+
+/// Record the current stall counters of the system, and of `cgroup` if given.
+/// Unavailable pressure files are left out.
+pub fn initial_pressure_stat(cgroup: Option<&Path>) -> PressureStat {
+    initial_pressure_stat_from(Path::new(PRESSURE_DIR), cgroup)
+}
+
+pub fn collect_pressure_info(stat: &mut PressureStat) -> Result<Vec<PressureEntry>> {
+    collect_pressure_info_from(stat, Instant::now())
+}
+
+pub fn do_pressure_statistic(samples: &[&[PressureEntry]]) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    for s in samples {
+        for p in s.iter() {
+            if !names.contains(&p.name.as_str()) {
+                names.push(&p.name);
+            }
+        }
+    }
+    if names.is_empty() {
+        return "Pressure:\t\tPSI is unavailable".to_string();
+    }
+    let bounds = [1.0, 5.0, 20.0, 50.0];
+    let labels = ["<1%", "1%-5%", "5%-20%", "20%-50%", ">=50%"];
+    let mut ret = Vec::new();
+    for name in names {
+        let entries: Vec<&PressureEntry> = samples.iter().flat_map(|s| s.iter()).filter(|p| p.name == name).collect();
+        let values = |f: fn(&PressureEntry) -> Option<f64>| -> Vec<f64> { entries.iter().filter_map(|p| f(p)).collect() };
+        ret.push(format_stat(&format!("{} some avg10:", name), &values(|p| Some(p.some_avg10)), &bounds, &labels));
+        ret.push(format_stat(&format!("{} some stall:", name), &values(|p| Some(p.some_stall)), &bounds, &labels));
+        if entries.iter().any(|p| p.full_stall.is_some()) {
+            ret.push(format_stat(&format!("{} full avg10:", name), &values(|p| p.full_avg10), &bounds, &labels));
+            ret.push(format_stat(&format!("{} full stall:", name), &values(|p| p.full_stall), &bounds, &labels));
+        }
+    }
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_collect_pressure_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let cgroup = dir.path().join("pemon.slice");
+        fs::create_dir(&cgroup).unwrap();
+        fs::write(dir.path().join("cpu"), "some avg10=1.00 avg60=0.50 avg300=0.10 total=1000000\n").unwrap();
+        fs::write(dir.path().join("io"), "some avg10=0.00 avg60=0.00 avg300=0.00 total=0\n\
+                                          full avg10=0.00 avg60=0.00 avg300=0.00 total=0\n").unwrap();
+        fs::write(cgroup.join("memory.pressure"), "garbage\n").unwrap();
+        fs::write(cgroup.join("io.pressure"), "some avg10=0.00 avg60=0.00 avg300=0.00 total=0\n").unwrap();
+        let mut stat = initial_pressure_stat_from(dir.path(), Some(&cgroup));
+        assert_eq!(stat.sources.len(), 3);
+        // The cgroup is gone before the first sample
+        fs::remove_file(cgroup.join("io.pressure")).unwrap();
+
+        fs::write(dir.path().join("cpu"), "some avg10=30.00 avg60=10.00 avg300=2.00 total=1500000\n").unwrap();
+        fs::write(dir.path().join("io"), "some avg10=5.00 avg60=1.00 avg300=0.20 total=200000\n\
+                                          full avg10=2.50 avg60=0.50 avg300=0.10 total=100000\n").unwrap();
+        let now = stat.last_time + Duration::from_secs(2);
+        let entries = collect_pressure_info_from(&mut stat, now).unwrap();
+        assert_eq!(entries, vec![
+            PressureEntry {
+                name: "CPU".to_string(), some_avg10: 30.0, some_avg60: 10.0, some_avg300: 2.0, some_stall: 25.0,
+                full_avg10: None, full_avg60: None, full_avg300: None, full_stall: None,
+            },
+            PressureEntry {
+                name: "IO".to_string(), some_avg10: 5.0, some_avg60: 1.0, some_avg300: 0.2, some_stall: 10.0,
+                full_avg10: Some(2.5), full_avg60: Some(0.5), full_avg300: Some(0.1), full_stall: Some(5.0),
+            },
+        ]);
+        let report = do_pressure_statistic(&[&entries]);
+        assert!(report.starts_with("CPU some avg10:\t\tavg: 30.00"));
+        assert!(!report.contains("CPU full"));
+        assert!(report.contains("IO full stall:\t\tavg: 5.00"));
+        assert_eq!(stat.sources.len(), 2);
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::errors::*;
+use crate::parser::*;
+use crate::statistic::*;
+use crate::util::*;
+
+const PROC_DIR: &str = "/proc";
+// Used when sysconf(_SC_CLK_TCK) fails, the value of every common architecture
+const DEFAULT_CLK_TCK: f64 = 100.0;
+
+struct ProcCounters {
+    starttime: u64,
+    ticks: u64,
+    read_bytes: u64,
+    write_bytes: u64,
+    voluntary: u64,
+    involuntary: u64,
+}
+
+struct ProcSample {
+    counters: ProcCounters,
+    rss: u64,
+    threads: u64,
+    cpus: Vec<usize>,
+}
+
+pub struct ProcessStat {
+    // e.g. "1234 (nginx)", for the report
+    pub name: String,
+    root: PathBuf,
+    pid: usize,
+    tree: bool,
+    procs: BTreeMap<usize, ProcCounters>,
+    ticks_per_sec: f64,
+    last_time: Instant,
+}
+
+/// The sum over the process and, with --pid-tree, its descendants.
+/// CPU usage is a percentage of one CPU, rates are per second.
+#[derive(PartialEq, Debug)]
+pub struct ProcessEntry {
+    pub processes: usize,
+    pub usage: f64,
+    pub rss: f64,
+    pub threads: u64,
+    pub voluntary_rate: f64,
+    pub involuntary_rate: f64,
+    pub read_mbps: f64,
+    pub write_mbps: f64,
+    // CPU each thread last ran on
+    pub cpus: Vec<usize>,
+}
+
+fn is_pid(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn read_pid_stat(path: &Path) -> Result<PidStat> {
+    parse_pid_stat(&path.to_string_lossy(), &fs::read_to_string(path)?)
+}
+
+// The process and all its descendants, parents before children
+fn get_pids(root: &Path, pid: usize, tree: bool) -> Vec<usize> {
+    if !root.join(pid.to_string()).exists() {
+        return Vec::new();
+    }
+    let mut result = vec![pid];
+    if !tree {
+        return result;
+    }
+    let mut children: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    if let Ok(entries) = fs::read_dir(root) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !is_pid(&name) {
+                continue;
+            }
+            // Processes may exit while the list is built
+            if let Ok(s) = read_pid_stat(&entry.path().join("stat")) {
+                children.entry(s.ppid).or_default().push(s.pid);
+            }
+        }
+    }
+    let mut i = 0;
+    while i < result.len() {
+        if let Some(c) = children.get(&result[i]) {
+            result.extend(c);
+        }
+        i += 1;
+    }
+    result
+}
+
+fn read_process(root: &Path, pid: usize) -> Result<ProcSample> {
+    let dir = root.join(pid.to_string());
+    let stat = read_pid_stat(&dir.join("stat"))?;
+    let status_file = dir.join("status");
+    let status = parse_pid_status(&status_file.to_string_lossy(), &fs::read_to_string(&status_file)?)?;
+    // io is only readable by the owner and root
+    let io_file = dir.join("io");
+    let io = match fs::read_to_string(&io_file) {
+        Ok(contents) => parse_key_values(&io_file.to_string_lossy(), &contents)?,
+        Err(_) => BTreeMap::new(),
+    };
+    let mut cpus = Vec::new();
+    if let Ok(entries) = fs::read_dir(dir.join("task")) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            if let Ok(s) = read_pid_stat(&entry.path().join("stat")) {
+                cpus.push(s.processor);
+            }
+        }
+    }
+    Ok(ProcSample {
+        counters: ProcCounters {
+            starttime: stat.starttime,
+            ticks: stat.utime + stat.stime,
+            read_bytes: io.get("read_bytes").cloned().unwrap_or(0),
+            write_bytes: io.get("write_bytes").cloned().unwrap_or(0),
+            voluntary: status.voluntary_ctxt_switches,
+            involuntary: status.nonvoluntary_ctxt_switches,
+        },
+        rss: status.rss,
+        threads: status.threads,
+        cpus,
+    })
+}
+
+fn initial_process_stat_from(root: &Path, pid: usize, tree: bool, ticks_per_sec: f64) -> Result<ProcessStat> {
+    let stat = read_pid_stat(&root.join(pid.to_string()).join("stat"))?;
+    let mut procs = BTreeMap::new();
+    for p in get_pids(root, pid, tree) {
+        if let Ok(s) = read_process(root, p) {
+            procs.insert(p, s.counters);
+        }
+    }
+    Ok(ProcessStat {
+        name: format!("{} ({})", pid, stat.comm),
+        root: root.to_path_buf(),
+        pid,
+        tree,
+        procs,
+        ticks_per_sec,
+        last_time: Instant::now(),
+    })
+}
+
+fn collect_process_info_from(stat: &mut ProcessStat, now: Instant) -> Option<ProcessEntry> {
+    let secs = now.duration_since(stat.last_time).as_secs_f64();
+    let rate = |new: u64, old: u64| new.saturating_sub(old) as f64 / secs;
+    let mut procs = BTreeMap::new();
+    let mut entry = ProcessEntry {
+        processes: 0, usage: 0.0, rss: 0.0, threads: 0, voluntary_rate: 0.0, involuntary_rate: 0.0,
+        read_mbps: 0.0, write_mbps: 0.0, cpus: Vec::new(),
+    };
+    for pid in get_pids(&stat.root, stat.pid, stat.tree) {
+        let s = match read_process(&stat.root, pid) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        entry.processes += 1;
+        entry.rss += s.rss as f64 / 1024.0;
+        entry.threads += s.threads;
+        entry.cpus.extend(s.cpus);
+        // A process which just entered the tree, or whose last read failed,
+        // only gets rates from the next sample. Its counters cover its whole
+        // life, not this interval.
+        let new = &s.counters;
+        if let Some(old) = stat.procs.get(&pid).filter(|o| o.starttime == new.starttime) {
+            entry.usage += rate(new.ticks, old.ticks) / stat.ticks_per_sec * 100.0;
+            entry.voluntary_rate += rate(new.voluntary, old.voluntary);
+            entry.involuntary_rate += rate(new.involuntary, old.involuntary);
+            entry.read_mbps += rate(new.read_bytes, old.read_bytes) / 1024.0 / 1024.0;
+            entry.write_mbps += rate(new.write_bytes, old.write_bytes) / 1024.0 / 1024.0;
+        }
+        procs.insert(pid, s.counters);
+    }
+    stat.procs = procs;
+    stat.last_time = now;
+    entry.cpus.sort_unstable();
+    if entry.processes == 0 { None } else { Some(entry) }
+}
+
+/// Start monitoring `pid`, and its descendants if `tree` is set.
+pub fn initial_process_stat(pid: usize, tree: bool) -> Result<ProcessStat> {
+    let ticks = unsafe { nix::libc::sysconf(nix::libc::_SC_CLK_TCK) };
+    let ticks_per_sec = if ticks > 0 { ticks as f64 } else { DEFAULT_CLK_TCK };
+    initial_process_stat_from(Path::new(PROC_DIR), pid, tree, ticks_per_sec)
+}
+
+/// None once the process has exited.
+pub fn collect_process_info(stat: &mut ProcessStat) -> Option<ProcessEntry> {
+    collect_process_info_from(stat, Instant::now())
+}
+
+/// Every sample comes with its time and the CPU temperature at that time,
+/// which are only listed one line per sample with `timeline`.
+pub fn do_process_statistic(name: &str, samples: &[(Duration, Option<f64>, &ProcessEntry)], timeline: bool) -> String {
+    if samples.is_empty() {
+        return format!("{}no samples", title_tabs(&format!("Process {}:", name)));
+    }
+    let values = |f: fn(&ProcessEntry) -> f64| -> Vec<f64> { samples.iter().map(|(_, _, p)| f(p)).collect() };
+    let mut ret = vec![
+        format!("Process {}:", name),
+        format_stat("Process CPU usage:", &values(|p| p.usage),
+                    &[10.0, 50.0, 90.0, 100.0], &["<10%", "10%-50%", "50%-90%", "90%-100%", ">=100%"]),
+        format_stat("Process RSS MiB:", &values(|p| p.rss),
+                    &[64.0, 512.0, 4096.0], &["<64", "64-512", "512-4096", ">=4096"]),
+        format_stat("Process threads:", &values(|p| p.threads as f64),
+                    &[2.0, 16.0, 128.0], &["1", "2-16", "16-128", ">=128"]),
+        format_stat("Process voluntary switches/s:", &values(|p| p.voluntary_rate),
+                    &[100.0, 1000.0, 10000.0], &["<100", "100-1k", "1k-10k", ">=10k"]),
+        format_stat("Process involuntary switches/s:", &values(|p| p.involuntary_rate),
+                    &[100.0, 1000.0, 10000.0], &["<100", "100-1k", "1k-10k", ">=10k"]),
+        format_stat("Process read MB/s:", &values(|p| p.read_mbps),
+                    &[1.0, 10.0, 100.0], &["<1", "1-10", "10-100", ">=100"]),
+        format_stat("Process write MB/s:", &values(|p| p.write_mbps),
+                    &[1.0, 10.0, 100.0], &["<1", "1-10", "10-100", ">=100"]),
+    ];
+    // Share of thread samples seen on each CPU
+    let mut cpus: BTreeMap<usize, usize> = BTreeMap::new();
+    for (_, _, p) in samples {
+        for c in &p.cpus {
+            *cpus.entry(*c).or_insert(0) += 1;
+        }
+    }
+    let total = cpus.values().sum::<usize>().max(1) as f64;
+    let shares: Vec<String> = cpus.iter()
+        .map(|(c, n)| format!("CPU{:02}: {:.2}%", c, *n as f64 / total * 100.0))
+        .collect();
+    ret.push(format!("{}{}", title_tabs("Process CPUs:"), shares.join(" | ")));
+    if !timeline {
+        return ret.join("\n");
+    }
+    for (time, temp, p) in samples {
+        let cpus: Vec<String> = p.cpus.iter().map(|c| c.to_string()).collect();
+        let temp = temp.map(|t| format!("CPU temperature: {:.1}°C | ", t)).unwrap_or_default();
+        ret.push(format!("Process {}:\t{}usage: {:.2}% | RSS: {:.2} MiB | threads: {} | CPUs: {}",
+                         format_elapsed(*time), temp, p.usage, p.rss, p.threads, cpus.join(",")));
+    }
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_process(root: &Path, pid: usize, ppid: usize, ticks: u64, tids: &[(usize, usize)]) {
+        let dir = root.join(pid.to_string());
+        fs::create_dir_all(dir.join("task")).unwrap();
+        let stat = |id: usize, cpu: usize| format!("{} (worker) S {} 0 0 0 -1 0 0 0 0 0 {} 0 0 0 20 0 {} 0 \
+                                                    0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 {} 0 0 0 0 0",
+                                                   id, ppid, ticks, tids.len(), cpu);
+        let status = format!("Name:\tworker\nVmRSS:\t{} kB\nThreads:\t{}\n\
+                              voluntary_ctxt_switches:\t{}\nnonvoluntary_ctxt_switches:\t0",
+                             ticks * 1024, tids.len(), ticks);
+        let io = format!("read_bytes: {}\nwrite_bytes: 0", ticks * 1024 * 1024);
+        write_attrs(&dir, &[("stat", &stat(pid, tids[0].1)), ("status", &status), ("io", &io)]);
+        for (tid, cpu) in tids {
+            write_attrs(&dir, &[(&format!("task/{}/stat", tid), &stat(*tid, *cpu))]);
+        }
+    }
+
+    #[test]
+    fn test_collect_process_info() {
+        let dir = tempfile::tempdir().unwrap();
+        write_process(dir.path(), 100, 1, 10, &[(100, 0), (101, 2)]);
+        write_process(dir.path(), 200, 100, 0, &[(200, 1)]);
+        write_process(dir.path(), 300, 1, 0, &[(300, 1)]);
+        assert_eq!(get_pids(dir.path(), 100, true), vec![100, 200]);
+        assert_eq!(get_pids(dir.path(), 100, false), vec![100]);
+
+        let mut stat = initial_process_stat_from(dir.path(), 100, true, 100.0).unwrap();
+        assert_eq!(stat.name, "100 (worker)");
+        write_process(dir.path(), 100, 1, 210, &[(100, 3), (101, 2)]);
+        write_process(dir.path(), 200, 100, 100, &[(200, 1)]);
+        let now = stat.last_time + Duration::from_secs(2);
+        let p = collect_process_info_from(&mut stat, now).unwrap();
+        assert_eq!(p.processes, 2);
+        assert_eq!(p.usage, 150.0);
+        assert_eq!(p.rss, 310.0);
+        assert_eq!(p.threads, 3);
+        assert_eq!(p.voluntary_rate, 150.0);
+        assert_eq!(p.read_mbps, 150.0);
+        assert_eq!(p.cpus, vec![1, 2, 3]);
+
+        let report = do_process_statistic(&stat.name, &[(Duration::from_secs(3), Some(70.5), &p)], true);
+        assert!(report.contains("Process CPUs:\t\tCPU01: 33.33% | CPU02: 33.33% | CPU03: 33.33%"));
+        assert!(report.ends_with("Process +00:00:03:\tCPU temperature: 70.5°C | usage: 150.00% | \
+                                  RSS: 310.00 MiB | threads: 3 | CPUs: 1,2,3"));
+        let report = do_process_statistic(&stat.name, &[(Duration::from_secs(3), Some(70.5), &p)], false);
+        assert!(report.ends_with("CPU03: 33.33%"));
+
+        // A long-lived process entering the tree doesn't add its lifetime to one interval
+        write_process(dir.path(), 300, 100, 5000, &[(300, 1)]);
+        write_process(dir.path(), 100, 1, 310, &[(100, 3), (101, 2)]);
+        let p = collect_process_info_from(&mut stat, now + Duration::from_secs(2)).unwrap();
+        assert_eq!((p.processes, p.usage), (3, 50.0));
+
+        fs::remove_dir_all(dir.path().join("100")).unwrap();
+        assert_eq!(collect_process_info_from(&mut stat, now + Duration::from_secs(2)), None);
+    }
+}
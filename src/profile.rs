@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use crate::errors::*;
+use crate::hwmon::HwmonEntry;
+use crate::util::*;
+
+const DMI_BOARD_NAME_FILE: &str = "/sys/class/dmi/id/board_name";
+const GENERIC_PROFILE: &str = include_str!("../profiles/generic.toml");
+const BUNDLED_PROFILES: [(&str, &str); 3] = [
+    ("asus-rog-strix-b450-i.toml", include_str!("../profiles/asus-rog-strix-b450-i.toml")),
+    ("asus-rog-strix-x570-e.toml", include_str!("../profiles/asus-rog-strix-x570-e.toml")),
+    ("gigabyte-b550-aorus-elite.toml", include_str!("../profiles/gigabyte-b550-aorus-elite.toml")),
+];
+/// The logical metrics a profile can map sensors to.
+pub const METRICS: [&str; 5] = ["cpu_temp", "mb_temp", "chipset_temp", "cpu_fan", "chassis_fan"];
+
+/// Map the sensor with this label, or this input (e.g. "temp1"), of a chip
+/// to a metric. The chip name may contain wildcards.
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MetricMapping {
+    pub metric: String,
+    pub chip: String,
+    pub label: Option<String>,
+    pub input: Option<String>,
+}
+
+/// When several mappings have the same metric, the first present sensor wins.
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BoardProfile {
+    pub name: String,
+    // DMI board names this profile is selected for
+    #[serde(default)]
+    pub boards: Vec<String>,
+    #[serde(default, rename = "metric")]
+    pub metrics: Vec<MetricMapping>,
+}
+
+impl MetricMapping {
+    fn matches(&self, e: &HwmonEntry) -> bool {
+        wildcard_match(&self.chip, &e.chip)
+            && self.label.as_ref().is_none_or(|l| *l == e.label)
+            && self.input.as_ref().is_none_or(|i| *i == e.attr)
+    }
+}
+
+pub fn parse_profile(file: &str, contents: &str) -> Result<BoardProfile> {
+    let profile: BoardProfile = toml::from_str(contents).chain_err(|| ErrorKind::LoadProfileFailed(file.to_string()))?;
+    for m in &profile.metrics {
+        if !METRICS.contains(&m.metric.as_str()) {
+            bail!("Unknown metric {} in {}, known metrics are: {}.", m.metric, file, METRICS.join(", "));
+        }
+        if m.label.is_none() && m.input.is_none() {
+            bail!("Metric {} in {} needs a label or an input.", m.metric, file);
+        }
+    }
+    Ok(profile)
+}
+
+pub fn load_profile(path: &Path) -> Result<BoardProfile> {
+    let file = path.to_string_lossy();
+    let contents = fs::read_to_string(path).chain_err(|| ErrorKind::LoadProfileFailed(file.to_string()))?;
+    parse_profile(&file, &contents)
+}
+
+pub fn get_board_name() -> Option<String> {
+    fs::read_to_string(DMI_BOARD_NAME_FILE).ok().map(|b| b.trim().to_string())
+}
+
+/// The bundled profile of `board`, or the generic one.
+pub fn select_profile(board: Option<&str>) -> BoardProfile {
+    if let Some(board) = board {
+        for (file, contents) in BUNDLED_PROFILES.iter() {
+            match parse_profile(file, contents) {
+                Ok(p) if p.boards.iter().any(|b| b.eq_ignore_ascii_case(board)) => return p,
+                Ok(_) => (),
+                Err(e) => warn!("Bundled profile {} is invalid: {}", file, e),
+            }
+        }
+    }
+    parse_profile("generic.toml", GENERIC_PROFILE).unwrap_or(BoardProfile {
+        name: "Generic".to_string(), boards: Vec::new(), metrics: Vec::new(),
+    })
+}
+
+/// The sensor reading of `metric`, with its limits, None if no sensor of
+/// the profile is present.
+pub fn get_metric<'a>(profile: &BoardProfile, metric: &str, entries: &'a [HwmonEntry]) -> Option<&'a HwmonEntry> {
+    profile.metrics.iter()
+        .filter(|m| m.metric == metric)
+        .find_map(|m| entries.iter().find(|e| m.matches(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hwmon::SensorKind;
+
+    fn entry(chip: &str, label: &str, attr: &str, value: f64) -> HwmonEntry {
+        HwmonEntry {
+            name: format!("{} {}", chip, label), device: chip.to_string(), chip: chip.to_string(), label: label.to_string(),
+            attr: attr.to_string(),
+            kind: SensorKind::Temperature, value, min: None, max: None, crit: None, alarm: false,
+        }
+    }
+
+    #[test]
+    fn test_bundled_profiles() {
+        for (file, contents) in BUNDLED_PROFILES.iter() {
+            assert!(parse_profile(file, contents).is_ok(), "{}", file);
+        }
+        assert_eq!(select_profile(Some("ROG STRIX B450-I GAMING")).name, "ASUS ROG STRIX B450-I GAMING");
+        assert_eq!(select_profile(Some("rog strix x570-e gaming")).name, "ASUS ROG STRIX X570-E GAMING");
+        assert_eq!(select_profile(Some("Unknown board")).name, "Generic");
+        assert_eq!(select_profile(None).name, "Generic");
+    }
+
+    #[test]
+    fn test_get_metric() {
+        let entries = vec![entry("nct6798", "fan1", "fan1", 900.0), entry("nct6798", "fan2", "fan2", 1200.0),
+                           entry("asusec", "CPU", "temp2", 52.0), entry("k10temp", "Tctl", "temp1", 55.5)];
+        let profile = select_profile(Some("ROG STRIX X570-E GAMING"));
+        assert_eq!(get_metric(&profile, "cpu_temp", &entries).map(|e| e.value), Some(52.0));
+        assert_eq!(get_metric(&profile, "cpu_fan", &entries).map(|e| e.value), Some(1200.0));
+        assert_eq!(get_metric(&profile, "mb_temp", &entries), None);
+        assert_eq!(get_metric(&select_profile(None), "cpu_temp", &entries).map(|e| e.value), Some(55.5));
+
+        let profile = parse_profile("custom.toml", "name = \"Custom\"\n[[metric]]\nmetric = \"cpu_temp\"\n\
+                                                   chip = \"nct67*\"\ninput = \"fan1\"\n").unwrap();
+        assert_eq!(get_metric(&profile, "cpu_temp", &entries).map(|e| e.value), Some(900.0));
+        assert!(parse_profile("bad.toml", "name = \"Bad\"\n[[metric]]\nmetric = \"gpu_temp\"\nchip = \"x\"\nlabel = \"y\"\n").is_err());
+        assert!(parse_profile("bad.toml", "name = \"Bad\"\n[[metric]]\nmetric = \"cpu_temp\"\nchip = \"x\"\n").is_err());
+        assert!(parse_profile("bad.toml", "name = 1\n").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use std::fs;
+use std::time::Instant;
+use crate::errors::*;
+use crate::parser::*;
+use crate::statistic::*;
+
+const PROC_STAT_FILE: &str = "/proc/stat";
+const LOADAVG_FILE: &str = "/proc/loadavg";
+
+pub struct SchedStat {
+    counters: ProcStat,
+    last_time: Instant,
+}
+
+/// Rates are per second.
+#[derive(PartialEq, Debug)]
+pub struct SchedEntry {
+    pub load1: f64,
+    pub load5: f64,
+    pub load15: f64,
+    pub procs_running: u64,
+    pub procs_blocked: u64,
+    pub threads: u64,
+    pub ctxt_rate: f64,
+    pub intr_rate: f64,
+    pub fork_rate: f64,
+}
+
+fn read_proc_stat() -> Result<ProcStat> {
+    parse_proc_stat(PROC_STAT_FILE, &fs::read_to_string(PROC_STAT_FILE)?)
+}
+
+fn get_sched_entry(old: &ProcStat, new: &ProcStat, load: &LoadAvg, secs: f64) -> SchedEntry {
+    let rate = |new: u64, old: u64| new.saturating_sub(old) as f64 / secs;
+    SchedEntry {
+        load1: load.load1,
+        load5: load.load5,
+        load15: load.load15,
+        procs_running: new.procs_running,
+        procs_blocked: new.procs_blocked,
+        threads: load.threads,
+        ctxt_rate: rate(new.ctxt, old.ctxt),
+        intr_rate: rate(new.intr, old.intr),
+        fork_rate: rate(new.processes, old.processes),
+    }
+}
+
+pub fn initial_sched_stat() -> Result<SchedStat> {
+    Ok(SchedStat { counters: read_proc_stat()?, last_time: Instant::now() })
+}
+
+pub fn collect_sched_info(stat: &mut SchedStat) -> Result<SchedEntry> {
+    let counters = read_proc_stat()?;
+    let load = parse_loadavg(LOADAVG_FILE, &fs::read_to_string(LOADAVG_FILE)?)?;
+    let now = Instant::now();
+    let secs = now.duration_since(stat.last_time).as_secs_f64();
+    let result = get_sched_entry(&stat.counters, &counters, &load, secs);
+    stat.counters = counters;
+    stat.last_time = now;
+    Ok(result)
+}
+
+pub fn do_sched_statistic(sched: &[&SchedEntry], cpu_num: usize) -> String {
+    let values = |f: fn(&SchedEntry) -> f64| -> Vec<f64> { sched.iter().map(|s| f(s)).collect() };
+    // Load and runnable tasks are judged against the number of CPUs
+    let n = cpu_num.max(1) as f64;
+    let load_bounds = [0.5 * n, n, 2.0 * n];
+    let load_labels = ["<0.5/CPU", "0.5-1/CPU", "1-2/CPU", ">=2/CPU"];
+    [
+        format_stat("Load average 1m:", &values(|s| s.load1), &load_bounds, &load_labels),
+        format_stat("Load average 5m:", &values(|s| s.load5), &load_bounds, &load_labels),
+        format_stat("Load average 15m:", &values(|s| s.load15), &load_bounds, &load_labels),
+        format_stat("Runnable tasks:", &values(|s| s.procs_running as f64), &load_bounds, &load_labels),
+        format_stat("Blocked tasks:", &values(|s| s.procs_blocked as f64),
+                    &[1.0, 4.0, 16.0], &["0", "1-4", "4-16", ">=16"]),
+        format_stat("Threads:", &values(|s| s.threads as f64),
+                    &[500.0, 2000.0, 10000.0], &["<500", "500-2k", "2k-10k", ">=10k"]),
+        format_stat("Context switches/s:", &values(|s| s.ctxt_rate),
+                    &[1000.0, 10000.0, 100000.0], &["<1k", "1k-10k", "10k-100k", ">=100k"]),
+        format_stat("Interrupts/s:", &values(|s| s.intr_rate),
+                    &[1000.0, 10000.0, 100000.0], &["<1k", "1k-10k", "10k-100k", ">=100k"]),
+        format_stat("Forks/s:", &values(|s| s.fork_rate),
+                    &[1.0, 10.0, 100.0], &["<1", "1-10", "10-100", ">=100"]),
+    ].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_sched_entry() {
+        let old = parse_proc_stat(PROC_STAT_FILE, "intr 1000 0 0\nctxt 5000\nprocesses 100\n\
+                                                   procs_running 1\nprocs_blocked 0\n").unwrap();
+        let new = parse_proc_stat(PROC_STAT_FILE, "intr 7000 0 0\nctxt 35000\nprocesses 106\n\
+                                                   procs_running 9\nprocs_blocked 2\n").unwrap();
+        let load = parse_loadavg(LOADAVG_FILE, "3.00 2.00 1.00 9/300 4242\n").unwrap();
+        let s = get_sched_entry(&old, &new, &load, 3.0);
+        assert_eq!(s.ctxt_rate, 10000.0);
+        assert_eq!(s.intr_rate, 2000.0);
+        assert_eq!(s.fork_rate, 2.0);
+        assert_eq!((s.procs_running, s.procs_blocked, s.threads), (9, 2, 300));
+        assert!(do_sched_statistic(&[&s], 4).starts_with("Load average 1m:\tavg: 3.00 | min: 3.00 | max: 3.00 | \
+                                                           <0.5/CPU: 0.00% | 0.5-1/CPU: 100.00%"));
+    }
+}
@@ -1,63 +1,98 @@
 use std::process::Command;
 use std::os::unix::process::CommandExt;
-use crate::errors::*;
 use nix::sys::signal::*;
+use crate::errors::*;
+use crate::hwmon::*;
+use crate::parser::*;
+use crate::profile::*;
+
+/// The value of a metric with the hardware limits of its sensor.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Reading {
+    pub value: f64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub crit: Option<f64>,
+    pub alarm: bool,
+}
+
+impl From<&HwmonEntry> for Reading {
+    fn from(e: &HwmonEntry) -> Reading {
+        Reading { value: e.value, min: e.min, max: e.max, crit: e.crit, alarm: e.alarm }
+    }
+}
 
+/// Temperatures are in °C. None if the board profile doesn't map the metric.
 #[derive(PartialEq, Debug)]
 pub struct Sensor {
-    pub cpu_temp: usize,
-    pub mb_temp: usize,
-    pub chipset_temp: usize,
-    pub cpu_fan_rpm: usize,
-    pub chassis_fan_rpm: usize,
+    pub cpu_temp: Option<Reading>,
+    pub mb_temp: Option<Reading>,
+    pub chipset_temp: Option<Reading>,
+    pub cpu_fan_rpm: Option<Reading>,
+    pub chassis_fan_rpm: Option<Reading>,
 }
 
-fn get_temperature(line: &String) -> Result<usize> {
-    let result;
-    if let Some(pos) = line.find(':') {
-        let temp_str = line[(pos + 1)..].trim();
-        if let Some(pos) = temp_str.find('.') {
-            // 1 means to skip the '+' before the temperature
-            let temp = temp_str[1..pos].parse::<usize>()?;
-            result = temp;
-        } else {
-            warn!("Parsing sensors output failed, illegal temperature line: {}", line);
-            bail!(ErrorKind::GetTempFailed);
-        }
-    } else {
-        warn!("Parsing sensors output failed, illegal temperature line: {}", line);
-        bail!(ErrorKind::GetTempFailed);
+impl Sensor {
+    /// The readings by the metric names alert rules refer to.
+    pub fn metrics(&self) -> [(&'static str, Option<Reading>); 5] {
+        [
+            ("cpu_temp", self.cpu_temp),
+            ("mb_temp", self.mb_temp),
+            ("chipset_temp", self.chipset_temp),
+            ("cpu_fan_rpm", self.cpu_fan_rpm),
+            ("chassis_fan_rpm", self.chassis_fan_rpm),
+        ]
+    }
+
+    /// The metrics which have no sensor.
+    pub fn unmapped(&self) -> Vec<&'static str> {
+        self.metrics().iter().filter(|(_, r)| r.is_none()).map(|(name, _)| *name).collect()
     }
-    Ok(result)
 }
 
-fn get_rpm(line: &String) -> Result<usize> {
-    let result;
-    if let Some(pos) = line.find(':') {
-        let temp_str = line[(pos + 1)..].trim();
-        if let Some(pos) = temp_str.find(' ') {
-            let rpm = temp_str[0..pos].parse::<usize>()?;
-            result = rpm;
-        } else {
-            warn!("Parsing sensors output failed, illegal rpm line: {}", line);
-            bail!(ErrorKind::GetRpmFailed);
+/// Where the sensor readings come from.
+pub enum SensorBackend {
+    Hwmon(HwmonStat),
+    // The "sensors" command, which applies the labels and compute lines of sensors.conf
+    LmSensors,
+}
+
+impl SensorBackend {
+    pub fn from_name(name: &str) -> Option<SensorBackend> {
+        match name {
+            "hwmon" => Some(SensorBackend::Hwmon(initial_hwmon_stat())),
+            "lm-sensors" => Some(SensorBackend::LmSensors),
+            _ => None,
         }
-    } else {
-        warn!("Parsing sensors output failed, illegal rpm line: {}", line);
-        bail!(ErrorKind::GetRpmFailed);
     }
-    Ok(result)
 }
 
-pub fn get_sensor_info() -> Result<Sensor> {
-    let mut cpu = 0;
-    let mut mb = 0;
-    let mut chipset = 0;
-    let mut cpu_fan = 0;
-    let mut cha_fan = 0;
+fn get_lm_sensors_entries(features: Vec<SensorsFeature>) -> Vec<HwmonEntry> {
+    let keys: Vec<(&str, &str, &str)> = features.iter().map(|f| (&f.device[..], &f.chip[..], &f.label[..])).collect();
+    let names = get_sensor_names(&keys);
+    // Inputs hwmon doesn't report on, e.g. "humidity1", are left out
+    features.into_iter().zip(names)
+        .filter_map(|(f, name)| SensorKind::from_attr(&f.attr).map(|kind| HwmonEntry {
+            name,
+            device: f.device,
+            chip: f.chip,
+            label: f.label,
+            attr: f.attr,
+            kind,
+            value: f.input,
+            min: f.min,
+            max: f.max,
+            crit: f.crit,
+            alarm: f.alarm,
+        }))
+        .collect()
+}
+
+pub fn get_lm_sensors_info() -> Result<Vec<HwmonEntry>> {
     let output;
     unsafe {
         output = Command::new("sensors")
+                 .arg("-j")
                  // pre_exec is unsafe function
                  .pre_exec(|| {
                      let mut set = SigSet::empty();
@@ -69,39 +104,29 @@ pub fn get_sensor_info() -> Result<Sensor> {
                  .output()?;
     }
     if !output.status.success() {
-        bail!("Running sensors failed. Sensor info is unavailable now.");
+        bail!("Running sensors failed. Is lm-sensors installed?");
     }
-
     let out = String::from_utf8_lossy(&output.stdout).into_owned();
-    for l in out.lines() {
-        let line = l.trim().to_string();
-        if line.len() == 0 {
-            continue;
-        }
+    Ok(get_lm_sensors_entries(parse_sensors_json("sensors -j", &out)?))
+}
 
-        if line.starts_with("CPU Temperature") {
-            cpu = get_temperature(&line)?;
-        }
-        if line.starts_with("Motherboard Temperature") {
-            mb = get_temperature(&line)?;
-        }
-        if line.starts_with("Chipset Temperature") {
-            chipset = get_temperature(&line)?;
-        }
-        if line.starts_with("CPU Fan") {
-            cpu_fan = get_rpm(&line)?;
-        }
-        if line.starts_with("Chassis Fan 1") {
-            cha_fan = get_rpm(&line)?;
-        }
+pub fn collect_sensor_readings(backend: &SensorBackend) -> Result<Vec<HwmonEntry>> {
+    match backend {
+        SensorBackend::Hwmon(stat) => Ok(collect_hwmon_info(stat)),
+        SensorBackend::LmSensors => get_lm_sensors_info(),
+    }
+}
+
+/// Pick the metrics of the board profile from the hwmon readings.
+pub fn get_sensor_info(profile: &BoardProfile, entries: &[HwmonEntry]) -> Sensor {
+    let get = |metric: &str| get_metric(profile, metric, entries).map(Reading::from);
+    Sensor {
+        cpu_temp: get("cpu_temp"),
+        mb_temp: get("mb_temp"),
+        chipset_temp: get("chipset_temp"),
+        cpu_fan_rpm: get("cpu_fan"),
+        chassis_fan_rpm: get("chassis_fan"),
     }
-    Ok(Sensor {
-        cpu_temp: cpu,
-        mb_temp: mb,
-        chipset_temp: chipset,
-        cpu_fan_rpm: cpu_fan,
-        chassis_fan_rpm: cha_fan,
-    })
 }
 
 #[cfg(test)]
@@ -110,9 +135,29 @@ mod tests {
 
     #[test]
     fn test_get_sensor() {
-        let result = get_sensor_info().unwrap();
-        assert_eq!(result.cpu_temp > 0, true);
-        println!("Sensor: cpu temp: {}, mb temp: {}, chipset temp: {}, cpu fan: {} RPM, chassis fan: {} RPM",
-                 result.cpu_temp, result.mb_temp, result.chipset_temp, result.cpu_fan_rpm, result.chassis_fan_rpm);
+        let profile = select_profile(get_board_name().as_deref());
+        let result = get_sensor_info(&profile, &collect_hwmon_info(&initial_hwmon_stat()));
+        assert!(result.cpu_temp.unwrap().value > 0.0);
+        println!("Sensor: {:?}", result);
+    }
+
+    #[test]
+    fn test_get_lm_sensors_entries() {
+        let features = parse_sensors_json("sensors -j", include_str!("../testdata/sensors-asus-b450-i.json")).unwrap();
+        let entries = get_lm_sensors_entries(features);
+        let profile = select_profile(Some("ROG STRIX B450-I GAMING"));
+        let sensor = get_sensor_info(&profile, &entries);
+        assert_eq!((sensor.cpu_temp.unwrap().value, sensor.cpu_fan_rpm.unwrap().value), (45.0, 1212.0));
+        let features = parse_sensors_json("sensors -j", include_str!("../testdata/sensors-nct6798.json")).unwrap();
+        let profile = parse_profile("nct.toml", "name = \"NCT\"\n[[metric]]\nmetric = \"cpu_fan\"\n\
+                                                chip = \"nct6798\"\ninput = \"fan2\"\n").unwrap();
+        let sensor = get_sensor_info(&profile, &get_lm_sensors_entries(features));
+        assert_eq!(sensor.cpu_fan_rpm, Some(Reading { value: 547.0, min: Some(600.0), max: None, crit: None, alarm: true }));
+        // The profile maps nothing else
+        assert_eq!(sensor.unmapped(), vec!["cpu_temp", "mb_temp", "chipset_temp", "chassis_fan_rpm"]);
+        assert_eq!(entries.iter().find(|e| e.attr == "in1").unwrap().kind, SensorKind::Voltage);
+        assert_eq!(SensorKind::from_attr("power1"), Some(SensorKind::Power));
+        assert_eq!(SensorKind::from_attr("intrusion0"), None);
+        assert_eq!(SensorKind::from_attr("temp"), None);
     }
 }
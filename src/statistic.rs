@@ -0,0 +1,44 @@
+/// Pad a report title with tabs so that the values start at the same column.
+pub fn title_tabs(title: &str) -> String {
+    let len = title.chars().count();
+    let tabs = if len < 8 { 3 } else if len < 16 { 2 } else { 1 };
+    format!("{}{}", title, "\t".repeat(tabs))
+}
+
+/// Format one report line: avg/min/max and the share of samples in each bucket.
+/// `bounds` are the ascending bucket boundaries, `labels` has one more entry.
+pub fn format_stat(title: &str, values: &[f64], bounds: &[f64], labels: &[&str]) -> String {
+    if values.is_empty() {
+        return format!("{}no samples", title_tabs(title));
+    }
+    let len = values.len() as f64;
+    let mut sum = 0.0;
+    let mut min = f64::MAX;
+    let mut max = f64::MIN;
+    let mut buckets = vec![0; labels.len()];
+    for v in values {
+        sum += v;
+        min = min.min(*v);
+        max = max.max(*v);
+        let b = bounds.iter().take_while(|bound| *v >= **bound).count();
+        buckets[b] += 1;
+    }
+    let mut ret = format!("{}avg: {:.2} | min: {:.2} | max: {:.2}", title_tabs(title), sum / len, min, max);
+    for (label, count) in labels.iter().zip(buckets) {
+        ret = format!("{} | {}: {:.2}%", ret, label, count as f64 / len * 100.0);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_stat() {
+        let values = [5.0, 10.0, 60.0, 100.0];
+        assert_eq!(format_stat("CPU00 usage:", &values, &[10.0, 50.0], &["<10%", "10%-50%", ">=50%"]),
+                   "CPU00 usage:\t\tavg: 43.75 | min: 5.00 | max: 100.00 | <10%: 25.00% | 10%-50%: 25.00% | >=50%: 50.00%");
+        assert_eq!(title_tabs("CPU00 frequency:"), "CPU00 frequency:\t");
+    }
+}
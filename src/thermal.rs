@@ -0,0 +1,229 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::errors::*;
+use crate::statistic::*;
+use crate::util::*;
+
+const THERMAL_SYS_DIR: &str = "/sys/class/thermal";
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum TripKind {
+    Passive,
+    Critical,
+    // "active" and "hot" trips don't slow the CPU down
+    Other,
+}
+
+struct ThermalZone {
+    name: String,
+    dir: PathBuf,
+    // Trip temperatures in °C
+    trips: Vec<(TripKind, f64)>,
+}
+
+struct CoolingDevice {
+    name: String,
+    dir: PathBuf,
+    max_state: u64,
+}
+
+pub struct ThermalStat {
+    zones: Vec<ThermalZone>,
+    cooling: Vec<CoolingDevice>,
+}
+
+/// Temperatures and margins are in °C. A margin is the distance to the
+/// nearest trip point of that kind, negative once it has been crossed.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ZoneEntry {
+    pub name: String,
+    pub temp: f64,
+    pub passive_margin: Option<f64>,
+    pub critical_margin: Option<f64>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct CoolingEntry {
+    pub name: String,
+    pub state: u64,
+    pub max_state: u64,
+}
+
+#[derive(PartialEq, Debug, Default)]
+pub struct ThermalEntry {
+    pub zones: Vec<ZoneEntry>,
+    pub cooling: Vec<CoolingEntry>,
+}
+
+fn read_name(dir: &Path, prefix: &str) -> Result<String> {
+    let id = dir.file_name().unwrap_or_default().to_string_lossy()[prefix.len()..].to_string();
+    Ok(format!("{} {}", fs::read_to_string(dir.join("type"))?.trim(), id))
+}
+
+fn read_temp(path: &Path) -> Result<f64> {
+    // In millidegrees Celsius
+    Ok(read_sysfs_i64(path)? as f64 / 1000.0)
+}
+
+fn read_trips(dir: &Path) -> Vec<(TripKind, f64)> {
+    let mut trips = Vec::new();
+    for k in 0.. {
+        let kind = match fs::read_to_string(dir.join(format!("trip_point_{}_type", k))) {
+            Ok(t) => t,
+            Err(_) => break,
+        };
+        let kind = match kind.trim() {
+            "passive" => TripKind::Passive,
+            "critical" => TripKind::Critical,
+            _ => TripKind::Other,
+        };
+        // Disabled trip points may not be readable
+        if let Ok(temp) = read_temp(&dir.join(format!("trip_point_{}_temp", k))) {
+            trips.push((kind, temp));
+        }
+    }
+    trips
+}
+
+fn initial_thermal_stat_from(root: &Path) -> ThermalStat {
+    let mut zones = Vec::new();
+    for dir in list_devices(root, "thermal_zone") {
+        // Skip zones whose sensor is disabled or broken
+        match (read_name(&dir, "thermal_zone"), read_temp(&dir.join("temp"))) {
+            (Ok(name), Ok(_)) => zones.push(ThermalZone { name, trips: read_trips(&dir), dir }),
+            _ => debug!("Skip thermal zone {}", dir.display()),
+        }
+    }
+    let mut cooling = Vec::new();
+    for dir in list_devices(root, "cooling_device") {
+        match (read_name(&dir, "cooling_device"), read_sysfs_u64(&dir.join("max_state"))) {
+            (Ok(name), Ok(max_state)) => cooling.push(CoolingDevice { name, max_state, dir }),
+            _ => debug!("Skip cooling device {}", dir.display()),
+        }
+    }
+    ThermalStat { zones, cooling }
+}
+
+fn margin(trips: &[(TripKind, f64)], kind: TripKind, temp: f64) -> Option<f64> {
+    trips.iter()
+        .filter(|(k, _)| *k == kind)
+        .map(|(_, t)| t - temp)
+        .fold(None, |acc: Option<f64>, m| Some(acc.map_or(m, |a| a.min(m))))
+}
+
+/// Find the thermal zones and cooling devices of the kernel thermal framework.
+pub fn initial_thermal_stat() -> ThermalStat {
+    initial_thermal_stat_from(Path::new(THERMAL_SYS_DIR))
+}
+
+pub fn collect_thermal_info(stat: &ThermalStat) -> ThermalEntry {
+    let mut result = ThermalEntry::default();
+    for z in &stat.zones {
+        // Some zones fail to read now and then, e.g. while the device sleeps
+        if let Ok(temp) = read_temp(&z.dir.join("temp")) {
+            result.zones.push(ZoneEntry {
+                name: z.name.clone(),
+                temp,
+                passive_margin: margin(&z.trips, TripKind::Passive, temp),
+                critical_margin: margin(&z.trips, TripKind::Critical, temp),
+            });
+        }
+    }
+    for c in &stat.cooling {
+        if let Ok(state) = read_sysfs_u64(&c.dir.join("cur_state")) {
+            result.cooling.push(CoolingEntry { name: c.name.clone(), state, max_state: c.max_state });
+        }
+    }
+    result
+}
+
+/// Every sample comes with its time, for the cooling state changes.
+pub fn do_thermal_statistic(samples: &[(Duration, &ThermalEntry)]) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    for (_, t) in samples {
+        for z in &t.zones {
+            if !names.contains(&z.name.as_str()) {
+                names.push(&z.name);
+            }
+        }
+    }
+    let mut ret = Vec::new();
+    if names.is_empty() {
+        ret.push("Thermal zones:\t\tno thermal zone".to_string());
+    }
+    let margin_bounds = [0.0, 5.0, 10.0, 20.0];
+    let margin_labels = ["crossed", "<5°C", "5°C-10°C", "10°C-20°C", ">=20°C"];
+    for name in names {
+        let zones: Vec<&ZoneEntry> = samples.iter().flat_map(|(_, t)| &t.zones).filter(|z| z.name == name).collect();
+        let temps: Vec<f64> = zones.iter().map(|z| z.temp).collect();
+        ret.push(format_stat(&format!("{} °C:", name), &temps,
+                             &[40.0, 60.0, 80.0, 90.0], &["<40°C", "40°C-60°C", "60°C-80°C", "80°C-90°C", ">=90°C"]));
+        let passive: Vec<f64> = zones.iter().filter_map(|z| z.passive_margin).collect();
+        if !passive.is_empty() {
+            ret.push(format_stat(&format!("{} to passive:", name), &passive, &margin_bounds, &margin_labels));
+        }
+        let critical: Vec<f64> = zones.iter().filter_map(|z| z.critical_margin).collect();
+        if !critical.is_empty() {
+            ret.push(format_stat(&format!("{} to critical:", name), &critical, &margin_bounds, &margin_labels));
+        }
+    }
+    let mut changes = Vec::new();
+    for pair in samples.windows(2) {
+        let (time, new) = pair[1];
+        for c in &new.cooling {
+            if let Some(old) = pair[0].1.cooling.iter().find(|o| o.name == c.name) {
+                if old.state != c.state {
+                    changes.push(format!("Cooling {}:\t{} | {}: {} -> {}/{}",
+                                         changes.len() + 1, format_elapsed(time), c.name, old.state, c.state, c.max_state));
+                }
+            }
+        }
+    }
+    if changes.is_empty() {
+        if samples.iter().any(|(_, t)| !t.cooling.is_empty()) {
+            ret.push("Cooling devices:\tno state change".to_string());
+        }
+    } else {
+        ret.push(format!("Cooling devices:\t{} state changes", changes.len()));
+        ret.extend(changes);
+    }
+    ret.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_thermal_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let zone = dir.path().join("thermal_zone0");
+        write_attrs(&zone, &[("type", "x86_pkg_temp"), ("temp", "78500"),
+                             ("trip_point_0_type", "passive"), ("trip_point_0_temp", "85000"),
+                             ("trip_point_1_type", "passive"), ("trip_point_1_temp", "80000"),
+                             ("trip_point_2_type", "critical"), ("trip_point_2_temp", "105000")]);
+        write_attrs(&dir.path().join("thermal_zone1"), &[("type", "battery"), ("temp", "-5250")]);
+        write_attrs(&dir.path().join("thermal_zone2"), &[("type", "broken")]);
+        let fan = dir.path().join("cooling_device0");
+        write_attrs(&fan, &[("type", "Fan"), ("cur_state", "0"), ("max_state", "10")]);
+
+        let stat = initial_thermal_stat_from(dir.path());
+        assert_eq!(stat.zones.len(), 2);
+        let first = collect_thermal_info(&stat);
+        assert_eq!(first.zones[0], ZoneEntry {
+            name: "x86_pkg_temp 0".to_string(), temp: 78.5, passive_margin: Some(1.5), critical_margin: Some(26.5),
+        });
+        assert_eq!(first.zones[1].temp, -5.25);
+        assert_eq!(first.zones[1].passive_margin, None);
+
+        write_attrs(&zone, &[("temp", "82000")]);
+        write_attrs(&fan, &[("cur_state", "3")]);
+        let second = collect_thermal_info(&stat);
+        assert_eq!(second.zones[0].passive_margin, Some(-2.0));
+        let report = do_thermal_statistic(&[(Duration::from_secs(3), &first), (Duration::from_secs(6), &second)]);
+        assert!(report.contains("x86_pkg_temp 0 to passive:\tavg: -0.25 | min: -2.00 | max: 1.50 | crossed: 50.00%"));
+        assert!(report.contains("battery 1 °C:\t\tavg: -5.25"));
+        assert!(report.ends_with("Cooling devices:\t1 state changes\nCooling 1:\t+00:00:06 | Fan 0: 0 -> 3/10"));
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+use crate::cpu::CpuInfoEntry;
+use crate::util::*;
+
+const CPU_SYS_DIR: &str = "/sys/devices/system/cpu";
+// A core busier than this is expected to run at least at its base frequency
+const BUSY_USAGE: f64 = 90.0;
+// CPU temperature within this many degrees of the limit counts as "near the limit"
+const TEMP_MARGIN: f64 = 5.0;
+// Without kernel counters, the heuristic must hold this many samples in a row
+const SUSTAINED_SAMPLES: usize = 2;
+
+#[derive(PartialEq, Debug)]
+pub struct ThrottleEpisode {
+    pub start: Duration,
+    pub duration: Duration,
+    pub cores: Vec<usize>,
+    // None without a CPU temperature sensor
+    pub peak_temp: Option<f64>,
+    // Whether the kernel throttle counters confirmed this episode
+    pub confirmed: bool,
+}
+
+pub struct ThrottleDetector {
+    base_freqs: BTreeMap<usize, f64>,
+    temp_limit: f64,
+    last_counts: BTreeMap<usize, u64>,
+    last_time: Duration,
+    current: Option<ThrottleEpisode>,
+    samples: usize,
+    pub episodes: Vec<ThrottleEpisode>,
+}
+
+fn read_base_freq(dir: &Path) -> f64 {
+    // intel_pstate and amd-pstate export the real base frequency in kHz
+    for name in &["base_frequency", "amd_pstate_nominal_freq"] {
+        if let Ok(khz) = read_sysfs_u64(&dir.join("cpufreq").join(name)) {
+            return khz as f64 / 1000.0;
+        }
+    }
+    // acpi-cpufreq leaves boost out of cpuinfo_max_freq, so it's the nominal P0
+    // state there. Other drivers include boost in it, then the base is unknown.
+    if read_sysfs_string(&dir.join("cpufreq/scaling_driver")).is_ok_and(|d| d == "acpi-cpufreq") {
+        if let Ok(khz) = read_sysfs_u64(&dir.join("cpufreq/cpuinfo_max_freq")) {
+            return khz as f64 / 1000.0;
+        }
+    }
+    0.0
+}
+
+fn read_throttle_count(dir: &Path) -> Option<u64> {
+    let mut found = false;
+    let mut count = 0;
+    for name in &["core_throttle_count", "package_throttle_count"] {
+        if let Ok(c) = read_sysfs_u64(&dir.join("thermal_throttle").join(name)) {
+            count += c;
+            found = true;
+        }
+    }
+    if found { Some(count) } else { None }
+}
+
+fn get_base_freqs_from(root: &Path, cpus: &[usize]) -> BTreeMap<usize, f64> {
+    cpus.iter().map(|i| (*i, read_base_freq(&root.join(format!("cpu{}", i))))).collect()
+}
+
+fn get_throttle_counts_from(root: &Path, cpus: &[usize]) -> BTreeMap<usize, u64> {
+    let mut result = BTreeMap::new();
+    for i in cpus {
+        // thermal_throttle only exists on Intel CPUs
+        if let Some(c) = read_throttle_count(&root.join(format!("cpu{}", i))) {
+            result.insert(*i, c);
+        }
+    }
+    result
+}
+
+/// Get the expected base frequency (MHz) of the given CPUs, 0 if it's unknown.
+pub fn get_base_freqs(cpus: &[usize]) -> BTreeMap<usize, f64> {
+    get_base_freqs_from(Path::new(CPU_SYS_DIR), cpus)
+}
+
+/// Get the kernel thermal throttle counters of the given CPUs.
+/// An empty map is returned if the kernel doesn't provide them.
+pub fn get_throttle_counts(cpus: &[usize]) -> BTreeMap<usize, u64> {
+    get_throttle_counts_from(Path::new(CPU_SYS_DIR), cpus)
+}
+
+impl ThrottleDetector {
+    pub fn new(base_freqs: BTreeMap<usize, f64>, temp_limit: f64, counts: BTreeMap<usize, u64>) -> ThrottleDetector {
+        ThrottleDetector {
+            base_freqs,
+            temp_limit,
+            last_counts: counts,
+            last_time: Duration::from_secs(0),
+            current: None,
+            samples: 0,
+            episodes: Vec::new(),
+        }
+    }
+
+    fn is_throttled(&self, cie: &CpuInfoEntry, cpu_temp: Option<f64>) -> bool {
+        let (base, temp) = match (self.base_freqs.get(&cie.id), cpu_temp) {
+            (Some(b), Some(t)) if *b > 0.0 => (*b, t),
+            _ => return false,
+        };
+        cie.usage >= BUSY_USAGE && cie.freq < base && temp + TEMP_MARGIN >= self.temp_limit
+    }
+
+    fn close(&mut self) {
+        if let Some(episode) = self.current.take() {
+            if episode.confirmed || self.samples >= SUSTAINED_SAMPLES {
+                self.episodes.push(episode);
+            }
+        }
+        self.samples = 0;
+    }
+
+    /// Feed one sample into the detector. Without `cpu_temp` only the kernel
+    /// counters can detect throttling.
+    pub fn update(&mut self, time: Duration, cpu_info: &[CpuInfoEntry], counts: &BTreeMap<usize, u64>,
+                  cpu_temp: Option<f64>) {
+        let mut cores = Vec::new();
+        let mut confirmed = false;
+        for cie in cpu_info {
+            let counted = match (counts.get(&cie.id), self.last_counts.get(&cie.id)) {
+                (Some(new), Some(old)) => new > old,
+                _ => false,
+            };
+            if counted {
+                confirmed = true;
+            }
+            if counted || self.is_throttled(cie, cpu_temp) {
+                cores.push(cie.id);
+            }
+        }
+        self.last_counts = counts.clone();
+
+        if cores.is_empty() {
+            self.close();
+        } else {
+            let start = self.last_time;
+            let episode = self.current.get_or_insert(ThrottleEpisode {
+                start,
+                duration: Duration::from_secs(0),
+                cores: Vec::new(),
+                peak_temp: None,
+                confirmed: false,
+            });
+            episode.duration = time - episode.start;
+            episode.confirmed |= confirmed;
+            if cpu_temp > episode.peak_temp {
+                episode.peak_temp = cpu_temp;
+            }
+            for c in cores {
+                if !episode.cores.contains(&c) {
+                    episode.cores.push(c);
+                }
+            }
+            episode.cores.sort();
+            self.samples += 1;
+        }
+        self.last_time = time;
+    }
+
+    /// Whether throttling can be detected at all, which needs the base
+    /// frequency of a CPU or the kernel counters.
+    pub fn available(&self) -> bool {
+        self.base_freqs.values().any(|b| *b > 0.0) || !self.last_counts.is_empty()
+    }
+
+    pub fn set_temp_limit(&mut self, temp_limit: f64) {
+        self.temp_limit = temp_limit;
+    }
+
+    /// Forget the episodes so far. One which is still ongoing is trimmed to
+    /// start at `time`.
+    pub fn reset(&mut self, time: Duration) {
+        self.episodes.clear();
+        if let Some(episode) = self.current.as_mut() {
+            episode.start = time;
+            episode.duration = Duration::from_secs(0);
+        }
+        self.last_time = time;
+    }
+
+    /// Close the episode which is still ongoing when monitoring stops.
+    pub fn finish(&mut self) {
+        self.close();
+    }
+}
+
+pub fn do_throttle_statistic(detector: &ThrottleDetector) -> String {
+    let episodes = &detector.episodes;
+    if episodes.is_empty() && !detector.available() {
+        return "Throttling:\t\tthrottle detection unavailable: base frequency unknown".to_string();
+    }
+    if episodes.is_empty() {
+        return "Throttling:\t\tno throttling episode detected".to_string();
+    }
+    let mut ret = format!("Throttling:\t\t{} episode(s) detected", episodes.len());
+    for (i, e) in episodes.iter().enumerate() {
+        let cores: Vec<String> = e.cores.iter().map(|c| format!("CPU{:02}", c)).collect();
+        let peak = e.peak_temp.map(|t| format!(" | peak temperature: {:.1}°C", t)).unwrap_or_default();
+        ret = format!("{}\nThrottling #{}:\t\tstart: {} | duration: {}s | cores: {}{}{}",
+                      ret, i + 1, format_elapsed(e.start), e.duration.as_secs(), cores.join(","), peak,
+                      if e.confirmed { " | confirmed by kernel counters" } else { "" });
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(freqs: &[f64], usage: f64) -> Vec<CpuInfoEntry> {
+        freqs.iter().enumerate().map(|(i, f)| CpuInfoEntry { id: i, freq: *f, usage, idle_states: Vec::new() }).collect()
+    }
+
+    fn map<T: Clone>(values: &[T]) -> BTreeMap<usize, T> {
+        values.iter().cloned().enumerate().collect()
+    }
+
+    #[test]
+    fn test_detect_throttling() {
+        let mut detector = ThrottleDetector::new(map(&[3600.0, 3600.0]), 95.0, BTreeMap::new());
+        detector.update(Duration::from_secs(3), &sample(&[4000.0, 4000.0], 100.0), &BTreeMap::new(), Some(80.0));
+        detector.update(Duration::from_secs(6), &sample(&[1800.0, 4000.0], 100.0), &BTreeMap::new(), Some(92.0));
+        detector.update(Duration::from_secs(9), &sample(&[1800.0, 1900.0], 100.0), &BTreeMap::new(), Some(94.5));
+        // Idle cores running slowly are not throttled
+        detector.update(Duration::from_secs(12), &sample(&[1800.0, 1800.0], 2.0), &BTreeMap::new(), Some(91.0));
+        // A single hot sample isn't sustained
+        detector.update(Duration::from_secs(15), &sample(&[1800.0, 1800.0], 100.0), &BTreeMap::new(), Some(93.0));
+        detector.finish();
+
+        assert_eq!(detector.episodes, vec![ThrottleEpisode {
+            start: Duration::from_secs(3),
+            duration: Duration::from_secs(6),
+            cores: vec![0, 1],
+            peak_temp: Some(94.5),
+            confirmed: false,
+        }]);
+
+        // An episode which is ongoing at a reset only counts from then on
+        let mut detector = ThrottleDetector::new(map(&[3600.0, 3600.0]), 95.0, BTreeMap::new());
+        detector.update(Duration::from_secs(3), &sample(&[1800.0, 1800.0], 100.0), &BTreeMap::new(), Some(93.0));
+        detector.update(Duration::from_secs(6), &sample(&[1800.0, 1800.0], 100.0), &BTreeMap::new(), Some(94.0));
+        detector.update(Duration::from_secs(9), &sample(&[4000.0, 4000.0], 100.0), &BTreeMap::new(), Some(80.0));
+        detector.update(Duration::from_secs(12), &sample(&[1800.0, 1800.0], 100.0), &BTreeMap::new(), Some(93.0));
+        detector.reset(Duration::from_secs(13));
+        detector.update(Duration::from_secs(15), &sample(&[1800.0, 1800.0], 100.0), &BTreeMap::new(), Some(92.0));
+        detector.finish();
+        let episodes: Vec<(u64, u64)> = detector.episodes.iter().map(|e| (e.start.as_secs(), e.duration.as_secs())).collect();
+        assert_eq!(episodes, vec![(13, 2)]);
+    }
+
+    #[test]
+    fn test_kernel_counters() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..2 {
+            write_attrs(&dir.path().join(format!("cpu{}/thermal_throttle", i)),
+                        &[("core_throttle_count", "5"), ("package_throttle_count", &i.to_string())]);
+        }
+        let counts = get_throttle_counts_from(dir.path(), &[0, 1, 2]);
+        assert_eq!(counts, map(&[5, 6]));
+        assert_eq!(get_base_freqs_from(dir.path(), &[0, 1]), map(&[0.0, 0.0]));
+        // The boost maximum is no base frequency
+        for i in 0..2 {
+            write_attrs(&dir.path().join(format!("cpu{}/cpufreq", i)), &[("cpuinfo_max_freq", "4650000")]);
+        }
+        write_attrs(dir.path(), &[("cpu1/cpufreq/amd_pstate_nominal_freq", "3600000")]);
+        assert_eq!(get_base_freqs_from(dir.path(), &[0, 1]), map(&[0.0, 3600.0]));
+        // acpi-cpufreq reports the nominal frequency as the maximum
+        write_attrs(dir.path(), &[("cpu0/cpufreq/scaling_driver", "acpi-cpufreq")]);
+        assert_eq!(get_base_freqs_from(dir.path(), &[0, 1]), map(&[4650.0, 3600.0]));
+        let unknown = ThrottleDetector::new(map(&[0.0]), 95.0, BTreeMap::new());
+        assert_eq!(do_throttle_statistic(&unknown), "Throttling:\t\tthrottle detection unavailable: base frequency unknown");
+
+        // Counters are authoritative even for a single sample at low temperature
+        let mut detector = ThrottleDetector::new(BTreeMap::new(), 95.0, counts);
+        detector.update(Duration::from_secs(3), &sample(&[4000.0, 4000.0], 50.0), &map(&[5, 9]), Some(60.0));
+        detector.finish();
+        assert_eq!(detector.episodes.len(), 1);
+        assert_eq!(detector.episodes[0].cores, vec![1]);
+        assert!(detector.episodes[0].confirmed);
+    }
+}
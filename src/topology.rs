@@ -0,0 +1,193 @@
+use std::fs;
+use std::path::Path;
+use crate::parser::parse_cpu_list;
+use crate::util::*;
+
+const CPU_SYS_DIR: &str = "/sys/devices/system/cpu";
+
+/// How CPUs are grouped in the report.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ReportLevel {
+    Thread,
+    Core,
+    Ccx,
+    Die,
+    Node,
+    Package,
+}
+
+impl ReportLevel {
+    pub fn from_name(name: &str) -> Option<ReportLevel> {
+        match name {
+            "thread" => Some(ReportLevel::Thread),
+            "core" => Some(ReportLevel::Core),
+            "ccx" => Some(ReportLevel::Ccx),
+            "die" => Some(ReportLevel::Die),
+            "node" => Some(ReportLevel::Node),
+            "package" => Some(ReportLevel::Package),
+            _ => None,
+        }
+    }
+}
+
+/// Where one logical CPU sits. Core, CCX and die are identified by the
+/// lowest CPU id sharing them, which is unique across packages.
+#[derive(PartialEq, Debug)]
+pub struct CpuTopology {
+    pub cpu: usize,
+    pub core: usize,
+    pub ccx: usize,
+    pub die: usize,
+    pub node: usize,
+    pub package: usize,
+}
+
+/// A named set of CPUs whose numbers are averaged in the report.
+#[derive(PartialEq, Debug)]
+pub struct CpuGroup {
+    pub name: String,
+    pub cpus: Vec<usize>,
+}
+
+fn first_cpu(path: &Path, cpu: usize) -> usize {
+    fs::read_to_string(path).ok()
+        .and_then(|l| parse_cpu_list(&path.to_string_lossy(), &l).ok())
+        .and_then(|v| v.into_iter().min())
+        .unwrap_or(cpu)
+}
+
+fn get_l3_first_cpu(dir: &Path, cpu: usize) -> usize {
+    for m in 0.. {
+        let index = dir.join(format!("cache/index{}", m));
+        if !index.exists() {
+            break;
+        }
+        if let Ok(3) = read_sysfs_u64(&index.join("level")) {
+            return first_cpu(&index.join("shared_cpu_list"), cpu);
+        }
+    }
+    cpu
+}
+
+fn get_node(dir: &Path) -> usize {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(n) = name.strip_prefix("node") {
+                if let Ok(node) = n.parse::<usize>() {
+                    return node;
+                }
+            }
+        }
+    }
+    0
+}
+
+fn get_cpu_topology_from(root: &Path, cpus: &[usize]) -> Vec<CpuTopology> {
+    let mut result = Vec::new();
+    for &cpu in cpus {
+        // Missing topology info (e.g. in some VMs) makes every CPU its own core
+        let dir = root.join(format!("cpu{}", cpu));
+        result.push(CpuTopology {
+            cpu,
+            core: first_cpu(&dir.join("topology/thread_siblings_list"), cpu),
+            ccx: get_l3_first_cpu(&dir, cpu),
+            // die_cpus_list is missing before Linux 5.2, then the package's CPUs are one die
+            die: first_cpu(&dir.join("topology/die_cpus_list"),
+                           first_cpu(&dir.join("topology/core_siblings_list"), cpu)),
+            node: get_node(&dir),
+            package: read_sysfs_u64(&dir.join("topology/physical_package_id")).unwrap_or(0) as usize,
+        });
+    }
+    result
+}
+
+/// Read the topology of the given CPUs.
+pub fn get_cpu_topology(cpus: &[usize]) -> Vec<CpuTopology> {
+    get_cpu_topology_from(Path::new(CPU_SYS_DIR), cpus)
+}
+
+/// Group CPUs for the report. Groups are numbered in the order they first appear.
+pub fn get_cpu_groups(topology: &[CpuTopology], level: ReportLevel) -> Vec<CpuGroup> {
+    let mut keys: Vec<usize> = Vec::new();
+    let mut result: Vec<CpuGroup> = Vec::new();
+    for t in topology {
+        let (prefix, key) = match level {
+            ReportLevel::Thread => ("CPU", t.cpu),
+            ReportLevel::Core => ("Core", t.core),
+            ReportLevel::Ccx => ("CCX", t.ccx),
+            ReportLevel::Die => ("Die", t.die),
+            ReportLevel::Node => ("Node", t.node),
+            ReportLevel::Package => ("Package", t.package),
+        };
+        match keys.iter().position(|k| *k == key) {
+            Some(pos) => result[pos].cpus.push(t.cpu),
+            None => {
+                let name = match level {
+                    ReportLevel::Thread => format!("{}{:02}", prefix, t.cpu),
+                    ReportLevel::Core => format!("{}{:02}", prefix, keys.len()),
+                    ReportLevel::Ccx | ReportLevel::Die => format!("{}{}", prefix, keys.len()),
+                    ReportLevel::Node | ReportLevel::Package => format!("{}{}", prefix, key),
+                };
+                keys.push(key);
+                result.push(CpuGroup { name, cpus: vec![t.cpu] });
+            },
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2 cores with 2 threads each sharing one L3, like a tiny Zen CCX
+    fn write_cpu(root: &Path, cpu: usize) {
+        let dir = root.join(format!("cpu{}", cpu));
+        fs::create_dir_all(dir.join("node0")).unwrap();
+        let siblings = ["0,2", "1,3"][cpu % 2];
+        write_attrs(&dir, &[("topology/thread_siblings_list", siblings), ("topology/physical_package_id", "0"),
+                            ("topology/core_siblings_list", "0-3"), ("cache/index0/level", "1"), ("cache/index0/shared_cpu_list", siblings),
+                            ("cache/index1/level", "3"), ("cache/index1/shared_cpu_list", "0-3")]);
+    }
+
+    #[test]
+    fn test_cpu_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        for cpu in 0..4 {
+            write_cpu(dir.path(), cpu);
+        }
+        let topology = get_cpu_topology_from(dir.path(), &[0, 1, 2, 3]);
+        assert_eq!(topology[2], CpuTopology { cpu: 2, core: 0, ccx: 0, die: 0, node: 0, package: 0 });
+
+        let cores = get_cpu_groups(&topology, ReportLevel::Core);
+        assert_eq!(cores, vec![
+            CpuGroup { name: "Core00".to_string(), cpus: vec![0, 2] },
+            CpuGroup { name: "Core01".to_string(), cpus: vec![1, 3] },
+        ]);
+        let ccx = get_cpu_groups(&topology, ReportLevel::Ccx);
+        assert_eq!(ccx, vec![CpuGroup { name: "CCX0".to_string(), cpus: vec![0, 1, 2, 3] }]);
+        assert_eq!(get_cpu_groups(&topology, ReportLevel::Thread)[3].name, "CPU03");
+    }
+
+    #[test]
+    fn test_die_groups() {
+        // One package of 2 dies with 4 CPUs each, like a Cascade Lake-AP
+        let dir = tempfile::tempdir().unwrap();
+        for cpu in 0..8 {
+            let die = ["0-3", "4-7"][cpu / 4];
+            write_attrs(&dir.path().join(format!("cpu{}/topology", cpu)), &[
+                ("thread_siblings_list", &cpu.to_string()), ("physical_package_id", "0"),
+                ("die_id", &(cpu / 4).to_string()), ("die_cpus_list", die), ("core_siblings_list", "0-7"),
+            ]);
+        }
+        let topology = get_cpu_topology_from(dir.path(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(topology[5].die, 4);
+        assert_eq!(get_cpu_groups(&topology, ReportLevel::Die), vec![
+            CpuGroup { name: "Die0".to_string(), cpus: vec![0, 1, 2, 3] },
+            CpuGroup { name: "Die1".to_string(), cpus: vec![4, 5, 6, 7] },
+        ]);
+        assert_eq!(get_cpu_groups(&topology, ReportLevel::Package).len(), 1);
+        assert_eq!(ReportLevel::from_name("die"), Some(ReportLevel::Die));
+    }
+}
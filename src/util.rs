@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::errors::*;
+
+/// Read a sysfs/procfs attribute which holds a single unsigned integer.
+pub fn read_sysfs_u64(path: &Path) -> Result<u64> {
+    let contents = fs::read_to_string(path)?;
+    Ok(contents.trim().parse::<u64>()?)
+}
+
+/// Read a sysfs attribute which holds a signed integer, e.g. a temperature.
+pub fn read_sysfs_i64(path: &Path) -> Result<i64> {
+    let contents = fs::read_to_string(path)?;
+    Ok(contents.trim().parse::<i64>()?)
+}
+
+/// Read a sysfs attribute which holds a string, e.g. a governor name.
+pub fn read_sysfs_string(path: &Path) -> Result<String> {
+    Ok(fs::read_to_string(path).chain_err(|| format!("Read {} failed.", path.display()))?.trim().to_string())
+}
+
+/// Write a value to a sysfs attribute.
+pub fn write_sysfs(path: &Path, value: &str) -> Result<()> {
+    fs::write(path, value).chain_err(|| format!("Write {} to {} failed.", value, path.display()))
+}
+
+/// The entries of `root` named `prefix` followed by a number, in numeric order.
+pub fn list_devices(root: &Path, prefix: &str) -> Vec<PathBuf> {
+    let mut result: Vec<(usize, PathBuf)> = match fs::read_dir(root) {
+        Ok(entries) => entries.filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name().to_string_lossy().into_owned();
+                name.strip_prefix(prefix).and_then(|id| id.parse::<usize>().ok()).map(|id| (id, e.path()))
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    result.sort();
+    result.into_iter().map(|(_, p)| p).collect()
+}
+
+/// Write sysfs-like attributes under `dir` for the tests, e.g.
+/// ("temp1_input", "45000"). Names may contain subdirectories.
+#[cfg(test)]
+pub fn write_attrs(dir: &Path, attrs: &[(&str, &str)]) {
+    for (name, value) in attrs {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap_or(dir)).unwrap();
+        fs::write(path, format!("{}\n", value)).unwrap();
+    }
+}
+
+/// Match a name against a shell-like pattern where '*' matches any
+/// sequence and '?' matches any single character.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    // Position of the last '*' and the name position it's matched up to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut i, mut j) = (0, 0);
+    while j < n.len() {
+        if i < p.len() && (p[i] == '?' || p[i] == n[j]) {
+            i += 1;
+            j += 1;
+        } else if i < p.len() && p[i] == '*' {
+            star = Some((i, j));
+            i += 1;
+        } else if let Some((si, sj)) = star {
+            i = si + 1;
+            j = sj + 1;
+            star = Some((si, sj + 1));
+        } else {
+            return false;
+        }
+    }
+    p[i..].iter().all(|c| *c == '*')
+}
+
+/// Format the time elapsed since monitoring started as "+HH:MM:SS".
+pub fn format_elapsed(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("+{:02}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_secs(0)), "+00:00:00");
+        assert_eq!(format_elapsed(Duration::from_secs(3723)), "+01:02:03");
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("veth*", "veth1a2b"));
+        assert!(wildcard_match("lo", "lo"));
+        assert!(!wildcard_match("lo", "lo0"));
+        assert!(wildcard_match("e*0", "eth0"));
+        assert!(wildcard_match("enp?s*", "enp3s0"));
+        assert!(!wildcard_match("docker*", "eth0"));
+    }
+}